@@ -0,0 +1,118 @@
+//! Measures the overhead of the counters kept by `StatsAlloc` when many
+//! threads allocate at once.
+//!
+//! At each number of threads, the time per operation of the system allocator
+//! is compared with that of the same allocator behind a single set of shared
+//! counters, as kept before the `sharded-counters` feature, and behind a
+//! `StatsAlloc`. Compare the counter layouts of `StatsAlloc` by running the
+//! benchmark with different features:
+//!
+//! ```text
+//! cargo bench --bench counters
+//! cargo bench --bench counters --features sharded-counters
+//! cargo bench --bench counters --features sharded-counters,relaxed-ordering
+//! ```
+
+extern crate stats_alloc;
+
+use stats_alloc::StatsAlloc;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const OPERATIONS: usize = 1_000_000;
+
+/// The system allocator behind counters shared by every thread, each updated
+/// on every request.
+#[derive(Default)]
+struct SharedCounters {
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    bytes_allocated: AtomicUsize,
+    bytes_deallocated: AtomicUsize,
+    bytes_live: AtomicUsize,
+    bytes_peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for SharedCounters {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocations.fetch_add(1, Ordering::SeqCst);
+        self.bytes_allocated.fetch_add(layout.size(), Ordering::SeqCst);
+        let live = self.bytes_live.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        self.bytes_peak.fetch_max(live, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocations.fetch_add(1, Ordering::SeqCst);
+        self.bytes_deallocated.fetch_add(layout.size(), Ordering::SeqCst);
+        self.bytes_live.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn churn<A: GlobalAlloc>(alloc: &A) {
+    let layouts = [
+        Layout::from_size_align(16, 8).unwrap(),
+        Layout::from_size_align(256, 8).unwrap(),
+        Layout::from_size_align(4_096, 8).unwrap(),
+    ];
+    for i in 0..OPERATIONS {
+        let layout = layouts[i % layouts.len()];
+        unsafe {
+            let ptr = black_box(alloc.alloc(layout));
+            alloc.dealloc(ptr, layout);
+        }
+    }
+}
+
+fn run<A: GlobalAlloc + Sync>(alloc: &A, threads: usize) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| churn(alloc));
+        }
+    });
+    start.elapsed()
+}
+
+fn main() {
+    let layout = match (cfg!(feature = "sharded-counters"), cfg!(feature = "relaxed-ordering")) {
+        (false, false) => "single shard, sequentially consistent",
+        (false, true) => "single shard, relaxed",
+        (true, false) => "sharded, sequentially consistent",
+        (true, true) => "sharded, relaxed",
+    };
+    println!("counter layout: {}", layout);
+
+    // Always compare at least two threads, even on a single processor
+    let max_threads = thread::available_parallelism().map_or(1, |n| n.get()).max(2);
+    let mut threads = 1;
+    loop {
+        let shared = SharedCounters::default();
+        let instrumented = StatsAlloc::new(System);
+        let baseline = run(&System, threads);
+        let per_op = |elapsed: Duration| elapsed.as_nanos() as f64 / (threads * OPERATIONS) as f64;
+        let overhead = |elapsed: Duration| per_op(elapsed) - per_op(baseline);
+        let shared_elapsed = run(&shared, threads);
+        let measured = run(&instrumented, threads);
+        println!(
+            "{:>3} threads: system {:>7.1} ns/op, shared counters {:>+7.1} ns/op, instrumented {:>+7.1} ns/op",
+            threads,
+            per_op(baseline),
+            overhead(shared_elapsed),
+            overhead(measured),
+        );
+        assert_eq!(shared.allocations.load(Ordering::SeqCst), threads * OPERATIONS);
+        assert_eq!(instrumented.stats().allocations, (threads * OPERATIONS) as u64);
+
+        if threads == max_threads {
+            break;
+        }
+        threads = (threads * 2).min(max_threads);
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+#[cfg(feature = "tags")]
+use tag;
+use thread;
+use AllocObserver;
+use StatsAlloc;
+
+/// With the `nightly` feature, a `StatsAlloc` is also an `Allocator` when the
+/// allocator it wraps is one, so that a single collection can be given its
+/// own instrumented allocator and measured in isolation.
+///
+/// Requests are counted in the same `Stats` as those made through
+/// `GlobalAlloc`, and are subject to the same limits, fault injection and
+/// observer. Growing and shrinking are counted as reallocations, and also in
+/// `Stats::grows` and `Stats::shrinks`.
+///
+/// ```
+/// #![feature(allocator_api)]
+/// # use stats_alloc::{Region, StatsAlloc};
+/// # use std::alloc::System;
+/// let alloc = StatsAlloc::new(System);
+/// let reg = Region::new(&alloc);
+/// let mut v = Vec::with_capacity_in(4, &alloc);
+/// v.extend_from_slice(&[1u32, 2, 3, 4, 5]);
+/// let change = reg.change();
+/// assert_eq!(change.allocations, 1);
+/// assert_eq!(change.grows, 1);
+/// ```
+unsafe impl<T: GlobalAlloc + Allocator, O: AllocObserver> Allocator for StatsAlloc<T, O> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let live = match self.admit(layout.size(), layout.size()) {
+            Some(live) => live,
+            None => {
+                thread::observe(|| self.observer.on_alloc_failure(layout));
+                return Err(AllocError);
+            },
+        };
+        match self.inner_allocate(layout, false) {
+            Ok(ptr) => {
+                self.raise_peak(live);
+                self.counters.record_alloc(layout.size());
+                self.sizes.record(layout.size());
+                thread::record_alloc(self.key(), layout.size());
+                #[cfg(feature = "tags")]
+                self.tags.record_alloc(
+                    unsafe { tag::tag_of(ptr.cast().as_ptr(), layout.size()) },
+                    layout.size(),
+                );
+                self.track_alloc(ptr.cast().as_ptr(), layout.size());
+                thread::observe(|| self.observer.on_alloc(ptr.cast().as_ptr(), layout));
+                Ok(ptr)
+            },
+            Err(error) => {
+                self.counters.release(layout.size());
+                self.record_failure();
+                thread::observe(|| self.observer.on_alloc_failure(layout));
+                Err(error)
+            },
+        }
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let live = match self.admit(layout.size(), layout.size()) {
+            Some(live) => live,
+            None => {
+                thread::observe(|| self.observer.on_alloc_failure(layout));
+                return Err(AllocError);
+            },
+        };
+        match self.inner_allocate(layout, true) {
+            Ok(ptr) => {
+                self.raise_peak(live);
+                self.counters.record_alloc(layout.size());
+                self.sizes.record(layout.size());
+                thread::record_alloc(self.key(), layout.size());
+                #[cfg(feature = "tags")]
+                self.tags.record_alloc(
+                    unsafe { tag::tag_of(ptr.cast().as_ptr(), layout.size()) },
+                    layout.size(),
+                );
+                self.track_alloc(ptr.cast().as_ptr(), layout.size());
+                thread::observe(|| self.observer.on_alloc_zeroed(ptr.cast().as_ptr(), layout));
+                Ok(ptr)
+            },
+            Err(error) => {
+                self.counters.release(layout.size());
+                self.record_failure();
+                thread::observe(|| self.observer.on_alloc_failure(layout));
+                Err(error)
+            },
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.counters.record_dealloc(layout.size());
+        thread::record_dealloc(self.key(), layout.size());
+        thread::observe(|| self.observer.on_dealloc(ptr.as_ptr(), layout));
+        #[cfg(feature = "tags")]
+        self.tags
+            .record_dealloc(tag::tag_of(ptr.as_ptr(), layout.size()), layout.size());
+        self.track_dealloc(ptr.as_ptr());
+        #[cfg(feature = "tags")]
+        tag::deallocate(&self.inner, ptr, layout);
+        #[cfg(not(feature = "tags"))]
+        self.inner.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(
+            ptr,
+            old_layout,
+            new_layout,
+            true,
+            false,
+            |ptr, old_layout, new_layout| self.inner.grow(ptr, old_layout, new_layout),
+        )
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(
+            ptr,
+            old_layout,
+            new_layout,
+            true,
+            true,
+            |ptr, old_layout, new_layout| self.inner.grow_zeroed(ptr, old_layout, new_layout),
+        )
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(
+            ptr,
+            old_layout,
+            new_layout,
+            false,
+            false,
+            |ptr, old_layout, new_layout| self.inner.shrink(ptr, old_layout, new_layout),
+        )
+    }
+}
+
+impl<T: GlobalAlloc + Allocator, O: AllocObserver> StatsAlloc<T, O> {
+    /// Allocates from the underlying allocator, with room for the tag of the
+    /// allocation when the `tags` feature is enabled.
+    fn inner_allocate(&self, layout: Layout, zeroed: bool) -> Result<NonNull<[u8]>, AllocError> {
+        let allocate = |layout| {
+            if zeroed {
+                self.inner.allocate_zeroed(layout)
+            } else {
+                self.inner.allocate(layout)
+            }
+        };
+        #[cfg(feature = "tags")]
+        return tag::allocate(layout, allocate);
+        #[cfg(not(feature = "tags"))]
+        allocate(layout)
+    }
+
+    /// Counts a grow, or a shrink if `growing` is false, made by `resize`,
+    /// which is called only if the request is admitted. With the `tags`
+    /// feature, `resize` is given the layouts of the blocks holding the
+    /// allocations and their tags, and `zeroed` tells whether new memory must
+    /// be zeroed.
+    fn resize<F>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        growing: bool,
+        zeroed: bool,
+        resize: F,
+    ) -> Result<NonNull<[u8]>, AllocError>
+    where
+        F: FnOnce(NonNull<u8>, Layout, Layout) -> Result<NonNull<[u8]>, AllocError>,
+    {
+        let (old_size, new_size) = (old_layout.size(), new_layout.size());
+        let growth = new_size.saturating_sub(old_size);
+        let live = match self.admit(new_size, growth) {
+            Some(live) => live,
+            None => {
+                thread::observe(|| self.observer.on_realloc_failure(ptr.as_ptr(), old_layout, new_size));
+                return Err(AllocError);
+            },
+        };
+        self.track_dealloc(ptr.as_ptr());
+        #[cfg(feature = "tags")]
+        let resized = unsafe { tag::resize(ptr, old_layout, new_layout, zeroed, resize) };
+        #[cfg(not(feature = "tags"))]
+        let resized = {
+            let _ = zeroed;
+            resize(ptr, old_layout, new_layout)
+        };
+        match resized {
+            Ok(new_ptr) => {
+                self.raise_peak(live);
+                self.counters.record_realloc(old_size, new_size);
+                self.sizes.record(new_size);
+                thread::record_realloc(self.key(), old_size, new_size);
+                if growing {
+                    self.counters.record_grow();
+                    thread::record_grow(self.key());
+                } else {
+                    self.counters.record_shrink();
+                    thread::record_shrink(self.key());
+                }
+                #[cfg(feature = "tags")]
+                self.record_tag_resize(new_ptr.cast().as_ptr(), old_size, new_size, growing);
+                self.track_alloc(new_ptr.cast().as_ptr(), new_size);
+                thread::observe(|| {
+                    self.observer
+                        .on_realloc(ptr.as_ptr(), new_ptr.cast().as_ptr(), old_layout, new_size)
+                });
+                Ok(new_ptr)
+            },
+            Err(error) => {
+                self.counters.release(growth);
+                self.record_failure();
+                thread::observe(|| self.observer.on_realloc_failure(ptr.as_ptr(), old_layout, new_size));
+                Err(error)
+            },
+        }
+    }
+
+    #[cfg(feature = "tags")]
+    fn record_tag_resize(&self, ptr: *mut u8, old_size: usize, new_size: usize, growing: bool) {
+        let tag = unsafe { tag::tag_of(ptr, new_size) };
+        self.tags.record_realloc(tag, old_size, new_size);
+        if growing {
+            self.tags.record_grow(tag);
+        } else {
+            self.tags.record_shrink(tag);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use std::{alloc::GlobalAlloc, error::Error, fmt, thread};
+use Region;
+use Stats;
+use StatsAlloc;
+
+/// Upper bounds on the fields of `Stats`, used to check that code stays within
+/// an allocation budget.
+///
+/// ```
+/// # use stats_alloc::{Limits, Stats};
+/// let limits = Limits::new().allocations(1).bytes_allocated(1_024);
+/// let stats = Stats {
+///     allocations: 2,
+///     bytes_allocated: 512,
+///     ..Stats::default()
+/// };
+/// let exceeded = limits.check(&stats).unwrap_err();
+/// assert_eq!(exceeded.to_string(), "allocation limits exceeded:\n    allocations: 2 (at most 1)");
+/// ```
+#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq)]
+pub struct Limits {
+    allocations: Option<u64>,
+    deallocations: Option<u64>,
+    reallocations: Option<u64>,
+    bytes_allocated: Option<u64>,
+    bytes_deallocated: Option<u64>,
+    bytes_live: Option<u64>,
+    failures: Option<u64>,
+}
+
+impl Limits {
+    /// Creates a set of limits which any statistics satisfy.
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    /// Limits `Stats::allocations`.
+    pub fn allocations(mut self, max: u64) -> Self {
+        self.allocations = Some(max);
+        self
+    }
+
+    /// Limits `Stats::deallocations`.
+    pub fn deallocations(mut self, max: u64) -> Self {
+        self.deallocations = Some(max);
+        self
+    }
+
+    /// Limits `Stats::reallocations`.
+    pub fn reallocations(mut self, max: u64) -> Self {
+        self.reallocations = Some(max);
+        self
+    }
+
+    /// Limits `Stats::bytes_allocated`.
+    pub fn bytes_allocated(mut self, max: u64) -> Self {
+        self.bytes_allocated = Some(max);
+        self
+    }
+
+    /// Limits `Stats::bytes_deallocated`.
+    pub fn bytes_deallocated(mut self, max: u64) -> Self {
+        self.bytes_deallocated = Some(max);
+        self
+    }
+
+    /// Limits `Stats::bytes_live`, which for a region is the growth in live
+    /// bytes.
+    pub fn bytes_live(mut self, max: u64) -> Self {
+        self.bytes_live = Some(max);
+        self
+    }
+
+    /// Limits `Stats::failures`.
+    pub fn failures(mut self, max: u64) -> Self {
+        self.failures = Some(max);
+        self
+    }
+
+    /// Checks `stats` against the limits, listing every field which exceeds
+    /// its limit.
+    pub fn check(&self, stats: &Stats) -> Result<(), LimitsExceeded> {
+        let checks = [
+            ("allocations", self.allocations, stats.allocations as i128),
+            ("deallocations", self.deallocations, stats.deallocations as i128),
+            ("reallocations", self.reallocations, stats.reallocations as i128),
+            ("bytes_allocated", self.bytes_allocated, stats.bytes_allocated as i128),
+            (
+                "bytes_deallocated",
+                self.bytes_deallocated,
+                stats.bytes_deallocated as i128,
+            ),
+            ("bytes_live", self.bytes_live, stats.bytes_live as i128),
+            ("failures", self.failures, stats.failures as i128),
+        ];
+        let violations: Vec<_> = checks
+            .iter()
+            .filter_map(|&(field, limit, actual)| match limit {
+                Some(limit) if actual > limit as i128 => Some(Violation { field, limit, actual }),
+                _ => None,
+            })
+            .collect();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(LimitsExceeded { violations })
+        }
+    }
+}
+
+/// A field of `Stats` which exceeded its limit.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Violation {
+    /// Name of the field in `Stats`
+    pub field: &'static str,
+    /// The limit on the field
+    pub limit: u64,
+    /// The value of the field
+    pub actual: i128,
+}
+
+/// The error returned when `Stats` exceed their `Limits`.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct LimitsExceeded {
+    violations: Vec<Violation>,
+}
+
+impl LimitsExceeded {
+    /// Returns each field which exceeded its limit.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+}
+
+impl fmt::Display for LimitsExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("allocation limits exceeded:")?;
+        for violation in &self.violations {
+            write!(
+                f,
+                "\n    {}: {} (at most {})",
+                violation.field, violation.actual, violation.limit
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LimitsExceeded {}
+
+/// What an `AllocGuard` does when its limits have been exceeded.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OnExceeded {
+    /// Panic, unless the thread is already panicking
+    Panic,
+    /// Print the exceeded limits to standard error
+    Log,
+}
+
+/// A `Region` which checks the allocations made while it is alive against a
+/// set of `Limits` when it is dropped.
+///
+/// ```should_panic
+/// # use stats_alloc::{AllocGuard, Limits, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// let _guard = AllocGuard::new(GLOBAL, Limits::new().bytes_allocated(512));
+/// let x: Vec<u8> = Vec::with_capacity(1_024);
+/// # drop(x);
+/// ```
+#[derive(Debug)]
+pub struct AllocGuard<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    region: Region<'a, T, O>,
+    limits: Limits,
+    on_exceeded: OnExceeded,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> AllocGuard<'a, T, O> {
+    /// Creates a guard which panics on drop if `limits` were exceeded.
+    pub fn new(alloc: &'a StatsAlloc<T, O>, limits: Limits) -> Self {
+        AllocGuard::with_action(alloc, limits, OnExceeded::Panic)
+    }
+
+    /// Creates a guard which takes the given action on drop if `limits` were
+    /// exceeded.
+    pub fn with_action(alloc: &'a StatsAlloc<T, O>, limits: Limits, on_exceeded: OnExceeded) -> Self {
+        AllocGuard {
+            region: Region::new(alloc),
+            limits,
+            on_exceeded,
+        }
+    }
+
+    /// Returns the region whose changes are checked.
+    pub fn region(&self) -> &Region<'a, T, O> {
+        &self.region
+    }
+
+    /// Checks the changes made so far against the limits.
+    pub fn check(&self) -> Result<(), LimitsExceeded> {
+        self.limits.check(&self.region.change())
+    }
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Drop for AllocGuard<'a, T, O> {
+    fn drop(&mut self) {
+        if let Err(exceeded) = self.check() {
+            match self.on_exceeded {
+                OnExceeded::Panic if !thread::panicking() => panic!("{}", exceeded),
+                OnExceeded::Panic => {},
+                OnExceeded::Log => eprintln!("{}", exceeded),
+            }
+        }
+    }
+}
+
+/// Runs a block, panicking if the calling thread made any allocations or
+/// reallocations in it, and returns the value of the block.
+///
+/// **Allocations made through every instrumented allocator are counted**, as
+/// by `thread_stats()`, and not only those of the global allocator. Those
+/// made by other threads, or through allocators which are not a `StatsAlloc`,
+/// are not seen. To check a single allocator, use `AllocGuard` or a
+/// `ThreadRegion`.
+///
+/// ```should_panic
+/// # #[macro_use] extern crate stats_alloc;
+/// # use stats_alloc::{StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// # fn main() {
+/// let sum = assert_no_alloc!({ (1..10).sum::<u32>() });
+/// assert_eq!(sum, 45);
+/// assert_no_alloc!({ vec![1, 2, 3] });
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_no_alloc {
+    ($body:block) => {
+        $crate::assert_allocations!(allocations = 0, reallocations = 0, $body)
+    };
+}
+
+/// Runs a block, panicking if the allocations made by the calling thread in
+/// it exceed the given limits, and returns the value of the block.
+///
+/// Each limit is written as `field = max` or `field <= max`, where `field` is
+/// a method of `Limits`. `max` is shorthand for `allocations` and `bytes` for
+/// `bytes_allocated`.
+///
+/// **As with `assert_no_alloc!`, allocations made by the calling thread
+/// through every instrumented allocator are counted**, and those of other
+/// threads are not seen.
+///
+/// ```
+/// # #[macro_use] extern crate stats_alloc;
+/// # use stats_alloc::{StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// # fn main() {
+/// let v = assert_allocations!(max = 1, bytes <= 64, reallocations = 0, {
+///     let mut v = Vec::with_capacity(16);
+///     v.extend_from_slice(&[1u32, 2, 3]);
+///     v
+/// });
+/// # drop(v);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_allocations {
+    ($($field:ident $op:tt $max:expr),+ , $body:block) => {{
+        let limits = $crate::Limits::new();
+        $(
+            $crate::__stats_alloc_limit_op!($op);
+            let limits = $crate::__stats_alloc_limit!(limits, $field, $max);
+        )+
+        let initial = $crate::thread_stats();
+        let result = $body;
+        let change = $crate::thread_stats() - initial;
+        if let Err(exceeded) = limits.check(&change) {
+            panic!("{}", exceeded);
+        }
+        result
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __stats_alloc_limit_op {
+    (=) => {};
+    (<=) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __stats_alloc_limit {
+    ($limits:expr, max, $max:expr) => {
+        $limits.allocations($max)
+    };
+    ($limits:expr, bytes, $max:expr) => {
+        $limits.bytes_allocated($max)
+    };
+    ($limits:expr, $field:ident, $max:expr) => {
+        $limits.$field($max)
+    };
+}
@@ -0,0 +1,69 @@
+use std::{
+    mem,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// Limits on `bytes_live` enforced by a `StatsAlloc`.
+///
+/// A limit of `usize::MAX` means that there is no limit.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    hard_limit: AtomicUsize,
+    soft_limit: AtomicUsize,
+    soft_limit_callback: AtomicUsize,
+    soft_limit_exceeded: AtomicBool,
+}
+
+impl Budget {
+    pub(crate) const fn new() -> Self {
+        Budget {
+            hard_limit: AtomicUsize::new(usize::MAX),
+            soft_limit: AtomicUsize::new(usize::MAX),
+            soft_limit_callback: AtomicUsize::new(0),
+            soft_limit_exceeded: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn hard_limit(&self) -> usize {
+        self.hard_limit.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_hard_limit(&self, limit: usize) {
+        self.hard_limit.store(limit, Ordering::SeqCst);
+    }
+
+    pub(crate) fn soft_limit(&self) -> usize {
+        self.soft_limit.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_soft_limit(&self, limit: usize, callback: Option<fn(usize)>) {
+        self.soft_limit.store(usize::MAX, Ordering::SeqCst);
+        self.soft_limit_callback
+            .store(callback.map_or(0, |callback| callback as usize), Ordering::SeqCst);
+        self.soft_limit_exceeded.store(false, Ordering::SeqCst);
+        self.soft_limit.store(limit, Ordering::SeqCst);
+    }
+
+    /// Invokes the soft limit callback if `bytes_live` has crossed the soft
+    /// limit for the first time.
+    #[inline]
+    pub(crate) fn check_soft_limit(&self, bytes_live: usize) {
+        if bytes_live > self.soft_limit.load(Ordering::Relaxed)
+            && !self.soft_limit_exceeded.swap(true, Ordering::SeqCst)
+        {
+            let callback = self.soft_limit_callback.load(Ordering::SeqCst);
+            if callback != 0 {
+                // Safety: only ever stored from a `fn(usize)`
+                let callback: fn(usize) = unsafe { mem::transmute(callback) };
+                callback(bytes_live);
+            }
+        }
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget::new()
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    convert::TryFrom,
+    sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+};
+use Stats;
+
+/// Number of `Region`s which may track their own peak at the same time.
+const PEAK_SLOTS: usize = 16;
+
+/// Number of shards the operation counters are spread across.
+pub(crate) const SHARDS: usize = if cfg!(feature = "sharded-counters") { 16 } else { 1 };
+
+/// Bytes of `bytes_live` a shard may hold back from the shared counter, in
+/// either direction.
+const BATCH: i64 = if cfg!(feature = "sharded-counters") {
+    64 * 1024
+} else {
+    0
+};
+
+/// Memory ordering used when updating and reading the operation counters.
+pub(crate) const ORDERING: Ordering = if cfg!(feature = "relaxed-ordering") {
+    Ordering::Relaxed
+} else {
+    Ordering::SeqCst
+};
+
+/// Returns the shard the calling thread updates.
+#[cfg(feature = "sharded-counters")]
+#[inline]
+pub(crate) fn shard_index() -> usize {
+    ::thread::shard_index() % SHARDS
+}
+
+/// Returns the shard the calling thread updates.
+#[cfg(not(feature = "sharded-counters"))]
+#[inline]
+pub(crate) fn shard_index() -> usize {
+    0
+}
+
+/// The shared counters behind a `StatsAlloc`.
+///
+/// Counters which only ever accumulate are kept in shards, each on its own
+/// cache line, and summed when read. With the `sharded-counters` feature each
+/// thread updates one of several shards, which avoids contention between
+/// threads allocating at the same time.
+///
+/// Without that feature `bytes_live` is a single counter, and the peaks are
+/// exact. With it, each shard also holds back changes to `bytes_live` until
+/// they reach `BATCH` bytes, so that most requests write only to their own
+/// shard. `bytes_live` is then exact when read, as the shards are summed, but
+/// the peaks are raised from an estimate which leaves out the changes held by
+/// other shards, and may fall short by up to `BATCH` bytes per shard.
+///
+/// The peaks are only written when they rise. `bytes_live` is signed, as the
+/// statistics of a thread which freed memory allocated by another may be
+/// added to it.
+///
+/// `SLOTS` is the number of peak slots, which only counters read by a
+/// `Region` need.
+#[derive(Debug)]
+pub(crate) struct Counters<const SLOTS: usize = PEAK_SLOTS> {
+    shards: [Shard; SHARDS],
+    /// Bytes live, less those held back by the shards.
+    bytes_live: AtomicI64,
+    bytes_peak: AtomicUsize,
+    /// Allocations live when the counters were last taken, which are no
+    /// longer counted in the shards.
+    live_taken: AtomicI64,
+    peak_slots: [AtomicUsize; SLOTS],
+    peak_slots_in_use: AtomicUsize,
+}
+
+#[derive(Default, Debug)]
+#[repr(align(128))]
+struct Shard {
+    allocations: AtomicU64,
+    deallocations: AtomicU64,
+    reallocations: AtomicU64,
+    grows: AtomicU64,
+    shrinks: AtomicU64,
+    bytes_allocated: AtomicU64,
+    bytes_deallocated: AtomicU64,
+    bytes_reallocated: AtomicI64,
+    failures: AtomicU64,
+    /// Change to `bytes_live` not yet applied to the shared counter.
+    bytes_live: AtomicI64,
+}
+
+impl<const SLOTS: usize> Counters<SLOTS> {
+    pub(crate) const fn new() -> Self {
+        Counters {
+            shards: [const { Shard::new() }; SHARDS],
+            bytes_live: AtomicI64::new(0),
+            bytes_peak: AtomicUsize::new(0),
+            live_taken: AtomicI64::new(0),
+            peak_slots: [const { AtomicUsize::new(0) }; SLOTS],
+            peak_slots_in_use: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn load(&self) -> Stats {
+        let mut stats = Stats::default();
+        for shard in &self.shards {
+            stats.allocations = stats.allocations.wrapping_add(shard.allocations.load(ORDERING));
+            stats.deallocations = stats.deallocations.wrapping_add(shard.deallocations.load(ORDERING));
+            stats.reallocations = stats.reallocations.wrapping_add(shard.reallocations.load(ORDERING));
+            stats.grows = stats.grows.wrapping_add(shard.grows.load(ORDERING));
+            stats.shrinks = stats.shrinks.wrapping_add(shard.shrinks.load(ORDERING));
+            stats.bytes_allocated = stats.bytes_allocated.wrapping_add(shard.bytes_allocated.load(ORDERING));
+            stats.bytes_deallocated = stats
+                .bytes_deallocated
+                .wrapping_add(shard.bytes_deallocated.load(ORDERING));
+            stats.bytes_reallocated = stats
+                .bytes_reallocated
+                .wrapping_add(shard.bytes_reallocated.load(ORDERING));
+            stats.failures = stats.failures.wrapping_add(shard.failures.load(ORDERING));
+        }
+        self.load_live(&mut stats);
+        stats
+    }
+
+    /// Sets the accumulating counters to zero, returning their previous
+    /// values. `bytes_live` is left alone, and the peak restarts from it.
+    pub(crate) fn take(&self) -> Stats {
+        let mut stats = Stats::default();
+        for shard in &self.shards {
+            stats.allocations = stats.allocations.wrapping_add(shard.allocations.swap(0, ORDERING));
+            stats.deallocations = stats.deallocations.wrapping_add(shard.deallocations.swap(0, ORDERING));
+            stats.reallocations = stats.reallocations.wrapping_add(shard.reallocations.swap(0, ORDERING));
+            stats.grows = stats.grows.wrapping_add(shard.grows.swap(0, ORDERING));
+            stats.shrinks = stats.shrinks.wrapping_add(shard.shrinks.swap(0, ORDERING));
+            stats.bytes_allocated = stats
+                .bytes_allocated
+                .wrapping_add(shard.bytes_allocated.swap(0, ORDERING));
+            stats.bytes_deallocated = stats
+                .bytes_deallocated
+                .wrapping_add(shard.bytes_deallocated.swap(0, ORDERING));
+            stats.bytes_reallocated = stats
+                .bytes_reallocated
+                .wrapping_add(shard.bytes_reallocated.swap(0, ORDERING));
+            stats.failures = stats.failures.wrapping_add(shard.failures.swap(0, ORDERING));
+        }
+        let taken = stats.allocations.wrapping_sub(stats.deallocations) as i64;
+        self.live_taken.fetch_add(taken, ORDERING);
+        self.load_live(&mut stats);
+        let live = stats.bytes_live.max(0) as usize;
+        stats.bytes_peak = self.bytes_peak.swap(live, ORDERING).max(live) as u64;
+        stats
+    }
+
+    fn load_live(&self, stats: &mut Stats) {
+        stats.live_allocations = stats
+            .allocations
+            .wrapping_sub(stats.deallocations)
+            .wrapping_add(self.live_taken.load(ORDERING) as u64) as i64;
+        stats.bytes_live = self.bytes_live();
+        stats.bytes_peak = self.bytes_peak.load(ORDERING).max(stats.bytes_live.max(0) as usize) as u64;
+    }
+
+    /// Returns the sum of the shared `bytes_live` and the changes held back
+    /// by the shards.
+    fn bytes_live(&self) -> i64 {
+        let held: i64 = self.shards.iter().map(|shard| shard.bytes_live.load(ORDERING)).sum();
+        self.bytes_live.load(ORDERING).wrapping_add(held)
+    }
+
+    #[inline]
+    fn shard(&self) -> &Shard {
+        &self.shards[shard_index()]
+    }
+
+    pub(crate) fn record_alloc(&self, size: usize) {
+        let shard = self.shard();
+        shard.allocations.fetch_add(1, ORDERING);
+        shard.bytes_allocated.fetch_add(size as u64, ORDERING);
+    }
+
+    pub(crate) fn record_dealloc(&self, size: usize) {
+        let shard = self.shard();
+        shard.deallocations.fetch_add(1, ORDERING);
+        shard.bytes_deallocated.fetch_add(size as u64, ORDERING);
+        self.release(size);
+    }
+
+    /// Records a reallocation. Growth must already have been reserved, while
+    /// any shrinkage is released here.
+    pub(crate) fn record_realloc(&self, old_size: usize, new_size: usize) {
+        let shard = self.shard();
+        shard.reallocations.fetch_add(1, ORDERING);
+        if new_size > old_size {
+            shard.bytes_allocated.fetch_add((new_size - old_size) as u64, ORDERING);
+        } else if new_size < old_size {
+            let difference = old_size - new_size;
+            shard.bytes_deallocated.fetch_add(difference as u64, ORDERING);
+            self.release(difference);
+        }
+        shard
+            .bytes_reallocated
+            .fetch_add(new_size as i64 - old_size as i64, ORDERING);
+    }
+
+    #[cfg(feature = "nightly")]
+    pub(crate) fn record_grow(&self) {
+        self.shard().grows.fetch_add(1, ORDERING);
+    }
+
+    #[cfg(feature = "nightly")]
+    pub(crate) fn record_shrink(&self) {
+        self.shard().shrinks.fetch_add(1, ORDERING);
+    }
+
+    pub(crate) fn record_failure(&self) {
+        self.shard().failures.fetch_add(1, ORDERING);
+    }
+
+    /// Adds `bytes` to `bytes_live`, unless that would take it over `limit`,
+    /// and returns the new `bytes_live`, or an estimate of it with the
+    /// `sharded-counters` feature. The peaks are left alone until
+    /// `raise_peak` is called.
+    ///
+    /// With a limit, the shared counter is updated directly, and checked
+    /// together with the changes held back by every shard. Reserving no
+    /// bytes always succeeds, even over the limit.
+    pub(crate) fn reserve(&self, bytes: usize, limit: usize) -> Option<usize> {
+        let bytes = i64::try_from(bytes).ok()?;
+        // Requests which do not grow `bytes_live` are never refused
+        let live = if limit != usize::MAX && bytes != 0 {
+            let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+            let held: i64 = self.shards.iter().map(|shard| shard.bytes_live.load(ORDERING)).sum();
+            let mut live = self.bytes_live.load(ORDERING);
+            loop {
+                let reserved = live
+                    .checked_add(bytes)
+                    .filter(|&reserved| reserved.saturating_add(held) <= limit)?;
+                match self
+                    .bytes_live
+                    .compare_exchange_weak(live, reserved, ORDERING, ORDERING)
+                {
+                    Ok(_) => break reserved.saturating_add(held),
+                    Err(actual) => live = actual,
+                }
+            }
+        } else if BATCH == 0 {
+            self.bytes_live.fetch_add(bytes, ORDERING).wrapping_add(bytes)
+        } else {
+            self.hold(bytes)
+        };
+        Some(live.max(0) as usize)
+    }
+
+    /// Adds `change` to the `bytes_live` held back by the calling thread's
+    /// shard, moving it to the shared counter once it reaches `BATCH` bytes,
+    /// and returns an estimate of `bytes_live`.
+    fn hold(&self, change: i64) -> i64 {
+        let held = &self.shard().bytes_live;
+        let pending = held.fetch_add(change, ORDERING).wrapping_add(change);
+        if pending.unsigned_abs() < BATCH as u64 {
+            return self.bytes_live.load(ORDERING).wrapping_add(pending);
+        }
+        let pending = held.swap(0, ORDERING);
+        self.bytes_live.fetch_add(pending, ORDERING).wrapping_add(pending)
+    }
+
+    /// Raises the peaks to `live`, the `bytes_live` returned by `reserve`
+    /// once the memory reserved has actually been allocated.
+    pub(crate) fn raise_peak(&self, live: usize) {
+        // Reading first leaves the cache lines shared while the peaks hold
+        if live > self.bytes_peak.load(ORDERING) {
+            self.bytes_peak.fetch_max(live, ORDERING);
+        }
+        let mut in_use = self.peak_slots_in_use.load(ORDERING);
+        while in_use != 0 {
+            let slot = in_use.trailing_zeros() as usize;
+            if live > self.peak_slots[slot].load(ORDERING) {
+                self.peak_slots[slot].fetch_max(live, ORDERING);
+            }
+            in_use &= in_use - 1;
+        }
+    }
+
+    /// Adds `bytes` which have already been allocated to `bytes_live`,
+    /// raising the peaks.
+    pub(crate) fn add_live(&self, bytes: usize) {
+        if let Some(live) = self.reserve(bytes, usize::MAX) {
+            self.raise_peak(live);
+        }
+    }
+
+    /// Subtracts `bytes` from `bytes_live`.
+    pub(crate) fn release(&self, bytes: usize) {
+        if BATCH == 0 {
+            self.bytes_live.fetch_sub(bytes as i64, ORDERING);
+        } else {
+            self.hold(-(bytes as i64));
+        }
+    }
+
+    /// Returns `bytes_live`, or zero if it is negative.
+    fn live(&self) -> usize {
+        self.bytes_live().max(0) as usize
+    }
+
+    /// Adds statistics gathered elsewhere, such as those of an exited thread.
+    pub(crate) fn add(&self, stats: &Stats) {
+        let shard = self.shard();
+        shard.allocations.fetch_add(stats.allocations, ORDERING);
+        shard.deallocations.fetch_add(stats.deallocations, ORDERING);
+        shard.reallocations.fetch_add(stats.reallocations, ORDERING);
+        shard.grows.fetch_add(stats.grows, ORDERING);
+        shard.shrinks.fetch_add(stats.shrinks, ORDERING);
+        shard.bytes_allocated.fetch_add(stats.bytes_allocated, ORDERING);
+        shard.bytes_deallocated.fetch_add(stats.bytes_deallocated, ORDERING);
+        shard.bytes_reallocated.fetch_add(stats.bytes_reallocated, ORDERING);
+        shard.failures.fetch_add(stats.failures, ORDERING);
+        self.bytes_live.fetch_add(stats.bytes_live, ORDERING);
+        self.bytes_peak.fetch_max(stats.bytes_peak as usize, ORDERING);
+    }
+
+    /// Returns the peak tracked by the given slot, or the all-time peak if
+    /// there is no slot.
+    pub(crate) fn peak(&self, slot: Option<usize>) -> usize {
+        let peak = match slot {
+            Some(slot) => self.peak_slots[slot].load(ORDERING),
+            None => self.bytes_peak.load(ORDERING),
+        };
+        if BATCH == 0 {
+            peak
+        } else {
+            peak.max(self.live())
+        }
+    }
+
+    /// Reserves a peak slot for a `Region`, starting it at the current
+    /// `bytes_live`. Returns `None` if every slot is taken.
+    pub(crate) fn claim_peak_slot(&self) -> Option<usize> {
+        let mut in_use = self.peak_slots_in_use.load(Ordering::SeqCst);
+        loop {
+            let free = !in_use & ((1 << SLOTS) - 1);
+            if free == 0 {
+                return None;
+            }
+            let slot = free.trailing_zeros() as usize;
+            self.peak_slots[slot].store(0, Ordering::SeqCst);
+            match self.peak_slots_in_use.compare_exchange_weak(
+                in_use,
+                in_use | 1 << slot,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    self.peak_slots[slot].fetch_max(self.live(), Ordering::SeqCst);
+                    return Some(slot);
+                },
+                Err(actual) => in_use = actual,
+            }
+        }
+    }
+
+    pub(crate) fn reset_peak_slot(&self, slot: usize) {
+        self.peak_slots[slot].store(self.live(), Ordering::SeqCst);
+    }
+
+    pub(crate) fn release_peak_slot(&self, slot: usize) {
+        self.peak_slots_in_use.fetch_and(!(1 << slot), Ordering::SeqCst);
+    }
+}
+
+impl<const SLOTS: usize> Default for Counters<SLOTS> {
+    fn default() -> Self {
+        Counters::new()
+    }
+}
+
+impl Shard {
+    const fn new() -> Self {
+        Shard {
+            allocations: AtomicU64::new(0),
+            deallocations: AtomicU64::new(0),
+            reallocations: AtomicU64::new(0),
+            grows: AtomicU64::new(0),
+            shrinks: AtomicU64::new(0),
+            bytes_allocated: AtomicU64::new(0),
+            bytes_deallocated: AtomicU64::new(0),
+            bytes_reallocated: AtomicI64::new(0),
+            failures: AtomicU64::new(0),
+            bytes_live: AtomicI64::new(0),
+        }
+    }
+}
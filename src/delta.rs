@@ -0,0 +1,142 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{iter, ops};
+use Stats;
+
+/// The signed difference between two `Stats`.
+///
+/// Subtracting one `Stats` from another with `-` wraps around where the
+/// right-hand side has the greater counts, and `checked_sub` or
+/// `saturating_sub` only tell or hide that. A delta, from `Stats::delta`, is
+/// signed instead, so it may be taken in either direction, for example to
+/// compare the changes of two `Region`s. Deltas can also be added up across
+/// many measurements.
+///
+/// `bytes_peak` has no meaningful difference, and is left out.
+///
+/// ```
+/// # use stats_alloc::{Stats, StatsDelta};
+/// let before = Stats { allocations: 10, bytes_allocated: 640, ..Stats::default() };
+/// let after = Stats { allocations: 7, bytes_allocated: 448, ..Stats::default() };
+/// let deltas = [after.delta(&before), after.delta(&before)];
+/// let total: StatsDelta = deltas.iter().sum();
+/// assert_eq!(total.allocations, -6);
+/// assert_eq!(total.bytes_allocated, -384);
+/// ```
+#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct StatsDelta {
+    /// Difference in `Stats::allocations`
+    pub allocations: i64,
+    /// Difference in `Stats::deallocations`
+    pub deallocations: i64,
+    /// Difference in `Stats::reallocations`
+    pub reallocations: i64,
+    /// Difference in `Stats::grows`
+    pub grows: i64,
+    /// Difference in `Stats::shrinks`
+    pub shrinks: i64,
+    /// Difference in `Stats::bytes_allocated`
+    pub bytes_allocated: i64,
+    /// Difference in `Stats::bytes_deallocated`
+    pub bytes_deallocated: i64,
+    /// Difference in `Stats::bytes_reallocated`
+    pub bytes_reallocated: i64,
+    /// Difference in `Stats::failures`
+    pub failures: i64,
+    /// Difference in `Stats::live_allocations`
+    pub live_allocations: i64,
+    /// Difference in `Stats::bytes_live`
+    pub bytes_live: i64,
+}
+
+/// Converts the counts of `Stats` into a delta from zero, wrapping any count
+/// beyond `i64::MAX`.
+impl From<Stats> for StatsDelta {
+    fn from(stats: Stats) -> Self {
+        StatsDelta {
+            allocations: stats.allocations as i64,
+            deallocations: stats.deallocations as i64,
+            reallocations: stats.reallocations as i64,
+            grows: stats.grows as i64,
+            shrinks: stats.shrinks as i64,
+            bytes_allocated: stats.bytes_allocated as i64,
+            bytes_deallocated: stats.bytes_deallocated as i64,
+            bytes_reallocated: stats.bytes_reallocated,
+            failures: stats.failures as i64,
+            live_allocations: stats.live_allocations,
+            bytes_live: stats.bytes_live,
+        }
+    }
+}
+
+impl ops::Add for StatsDelta {
+    type Output = StatsDelta;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl ops::AddAssign for StatsDelta {
+    fn add_assign(&mut self, rhs: Self) {
+        self.allocations = self.allocations.wrapping_add(rhs.allocations);
+        self.deallocations = self.deallocations.wrapping_add(rhs.deallocations);
+        self.reallocations = self.reallocations.wrapping_add(rhs.reallocations);
+        self.grows = self.grows.wrapping_add(rhs.grows);
+        self.shrinks = self.shrinks.wrapping_add(rhs.shrinks);
+        self.bytes_allocated = self.bytes_allocated.wrapping_add(rhs.bytes_allocated);
+        self.bytes_deallocated = self.bytes_deallocated.wrapping_add(rhs.bytes_deallocated);
+        self.bytes_reallocated = self.bytes_reallocated.wrapping_add(rhs.bytes_reallocated);
+        self.failures = self.failures.wrapping_add(rhs.failures);
+        self.live_allocations = self.live_allocations.wrapping_add(rhs.live_allocations);
+        self.bytes_live = self.bytes_live.wrapping_add(rhs.bytes_live);
+    }
+}
+
+impl ops::Sub for StatsDelta {
+    type Output = StatsDelta;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + -rhs
+    }
+}
+
+impl ops::SubAssign for StatsDelta {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self += -rhs;
+    }
+}
+
+impl ops::Neg for StatsDelta {
+    type Output = StatsDelta;
+
+    fn neg(self) -> Self::Output {
+        StatsDelta {
+            allocations: self.allocations.wrapping_neg(),
+            deallocations: self.deallocations.wrapping_neg(),
+            reallocations: self.reallocations.wrapping_neg(),
+            grows: self.grows.wrapping_neg(),
+            shrinks: self.shrinks.wrapping_neg(),
+            bytes_allocated: self.bytes_allocated.wrapping_neg(),
+            bytes_deallocated: self.bytes_deallocated.wrapping_neg(),
+            bytes_reallocated: self.bytes_reallocated.wrapping_neg(),
+            failures: self.failures.wrapping_neg(),
+            live_allocations: self.live_allocations.wrapping_neg(),
+            bytes_live: self.bytes_live.wrapping_neg(),
+        }
+    }
+}
+
+impl iter::Sum for StatsDelta {
+    fn sum<I: Iterator<Item = StatsDelta>>(iter: I) -> Self {
+        iter.fold(StatsDelta::default(), ops::Add::add)
+    }
+}
+
+impl<'a> iter::Sum<&'a StatsDelta> for StatsDelta {
+    fn sum<I: Iterator<Item = &'a StatsDelta>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
@@ -0,0 +1,204 @@
+use profiler::{HeapProfile, Symbol};
+use std::{
+    alloc::GlobalAlloc,
+    collections::HashMap,
+    env,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process,
+};
+use StatsAlloc;
+
+impl HeapProfile {
+    /// Writes the profile in the JSON format of DHAT, which the viewer at
+    /// `dh_view.html` in Valgrind, or hosted online, can open.
+    ///
+    /// Each backtrace records its total, maximum, at-peak and live bytes and
+    /// allocations, and the total lifetime of its allocations. Backtraces are
+    /// symbolized as they are written. The counts are exact only while every
+    /// allocation is sampled, with a sample interval of 1.
+    ///
+    /// ```no_run
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::System;
+    /// # use std::fs::File;
+    /// let alloc = StatsAlloc::new(System);
+    /// alloc.start_profiling(1);
+    /// // ...
+    /// let file = File::create("dhat-heap.json")?;
+    /// alloc.heap_profile().write_dhat(file)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn write_dhat<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(self.to_dhat().as_bytes())?;
+        writer.flush()
+    }
+
+    fn to_dhat(&self) -> String {
+        let mut frames = Frames::default();
+        let mut json = String::new();
+        // Arguments need not be valid UTF-8, and this may run as the program ends
+        let command = env::args_os()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join(" ");
+        json.push_str("{\"dhatFileVersion\":2,\"mode\":\"rust-heap\",\"verb\":\"Allocated\"");
+        json.push_str(",\"bklt\":true,\"bkacc\":false,\"tu\":\"µs\",\"Mtu\":\"µs\",\"tuth\":10");
+        json.push_str(",\"cmd\":");
+        push_str(&mut json, &command);
+        let _ = write!(
+            json,
+            ",\"pid\":{},\"tg\":{},\"te\":{},\"pps\":[",
+            process::id(),
+            self.peak_time().as_micros(),
+            self.duration().as_micros()
+        );
+        for (i, sample) in self.samples().iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "\n{{\"tb\":{},\"tbk\":{},\"tl\":{},\"mb\":{},\"mbk\":{}",
+                sample.total_bytes(),
+                sample.total_allocations(),
+                sample.total_lifetime().as_micros(),
+                sample.max_bytes(),
+                sample.max_allocations()
+            );
+            let _ = write!(
+                json,
+                ",\"gb\":{},\"gbk\":{},\"eb\":{},\"ebk\":{},\"fs\":[",
+                sample.bytes_at_peak(),
+                sample.allocations_at_peak(),
+                sample.bytes(),
+                sample.allocations()
+            );
+            for (j, symbol) in sample.resolve().iter().enumerate() {
+                if j > 0 {
+                    json.push(',');
+                }
+                let _ = write!(json, "{}", frames.index(symbol));
+            }
+            json.push_str("]}");
+        }
+        json.push_str("\n],\"ftbl\":[");
+        for (i, frame) in frames.table.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push('\n');
+            push_str(&mut json, frame);
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+}
+
+/// The table of frames shared between backtraces.
+struct Frames {
+    table: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Frames {
+            // The first frame stands for the root of every backtrace
+            table: vec!["[root]".to_owned()],
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl Frames {
+    fn index(&mut self, symbol: &Symbol) -> usize {
+        let mut frame = format!("{:#x}: ", symbol.address);
+        match symbol.name {
+            Some(ref name) => frame.push_str(name),
+            None => frame.push_str("???"),
+        }
+        if let Some(ref file) = symbol.file {
+            let _ = write!(frame, " ({}:{})", file.display(), symbol.line.unwrap_or(0));
+        }
+        if let Some(&index) = self.indices.get(&frame) {
+            return index;
+        }
+        let index = self.table.len();
+        self.table.push(frame.clone());
+        self.indices.insert(frame, index);
+        index
+    }
+}
+
+/// Appends `string` to `json` as a quoted JSON string.
+fn push_str(json: &mut String, string: &str) {
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Writes a DHAT profile of an allocator to a file when dropped.
+///
+/// Creating the output starts the profiler with a sample interval of 1, so
+/// that every allocation is recorded, unless it is already running. Hold it
+/// for the whole of `main` to write the profile as the program exits.
+///
+/// ```no_run
+/// # use stats_alloc::{DhatOutput, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// fn main() {
+///     let _dhat = DhatOutput::new(GLOBAL, "dhat-heap.json");
+///     // ...
+/// }
+/// ```
+#[derive(Debug)]
+#[must_use = "the profile is written when the output is dropped"]
+pub struct DhatOutput<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    alloc: &'a StatsAlloc<T, O>,
+    path: PathBuf,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> DhatOutput<'a, T, O> {
+    /// Starts profiling `alloc`, to be written to the file at `path`.
+    pub fn new<P: Into<PathBuf>>(alloc: &'a StatsAlloc<T, O>, path: P) -> Self {
+        if alloc.sample_interval().is_none() {
+            alloc.start_profiling(1);
+        }
+        DhatOutput {
+            alloc,
+            path: path.into(),
+        }
+    }
+
+    /// Writes the profile taken so far to the file.
+    pub fn write(&self) -> io::Result<()> {
+        self.alloc.heap_profile().write_dhat(File::create(&self.path)?)
+    }
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Drop for DhatOutput<'a, T, O> {
+    fn drop(&mut self) {
+        if let Err(error) = self.write() {
+            eprintln!("failed to write DHAT profile: {}", error);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use Stats;
+
+impl Stats {
+    /// Returns the bytes allocated less those deallocated.
+    pub fn net_bytes(&self) -> i64 {
+        (self.bytes_allocated as i64).wrapping_sub(self.bytes_deallocated as i64)
+    }
+
+    /// Returns the mean number of bytes requested per allocation, or zero if
+    /// there were none.
+    pub fn average_allocation_size(&self) -> f64 {
+        match self.allocations {
+            0 => 0.0,
+            allocations => self.bytes_allocated as f64 / allocations as f64,
+        }
+    }
+
+    /// Returns the number of reallocations per allocation, or zero if there
+    /// were no allocations.
+    pub fn reallocation_ratio(&self) -> f64 {
+        match self.allocations {
+            0 => 0.0,
+            allocations => self.reallocations as f64 / allocations as f64,
+        }
+    }
+}
+
+/// Formats the statistics on one line, with byte counts in binary units.
+///
+/// ```
+/// # use stats_alloc::Stats;
+/// let stats = Stats {
+///     allocations: 4,
+///     deallocations: 3,
+///     bytes_allocated: 3_072,
+///     bytes_deallocated: 1_024,
+///     ..Stats::default()
+/// };
+/// assert!(stats.to_string().starts_with("4 allocations (3.0 KiB), 3 deallocations (1.0 KiB)"));
+/// ```
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations ({}), {} deallocations ({}), {} reallocations ({}), {} failures; {} live allocations of \
+             {} (peak {}), {} net; average allocation {}, reallocation ratio {:.2}",
+            self.allocations,
+            Bytes(self.bytes_allocated as f64),
+            self.deallocations,
+            Bytes(self.bytes_deallocated as f64),
+            self.reallocations,
+            Bytes(self.bytes_reallocated as f64),
+            self.failures,
+            self.live_allocations,
+            Bytes(self.bytes_live as f64),
+            Bytes(self.bytes_peak as f64),
+            Bytes(self.net_bytes() as f64),
+            Bytes(self.average_allocation_size()),
+            self.reallocation_ratio()
+        )
+    }
+}
+
+/// Several labelled `Stats` rendered side by side, one column each.
+///
+/// ```
+/// # use stats_alloc::{Region, StatsAlloc, StatsTable};
+/// # use std::alloc::System;
+/// let alloc = StatsAlloc::new(System);
+/// let reg = Region::new(&alloc);
+/// // ...
+/// let first = reg.change();
+/// // ...
+/// let second = reg.change();
+/// println!("{}", StatsTable::new().column("first", first).column("second", second));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StatsTable {
+    columns: Vec<(String, Stats)>,
+}
+
+impl StatsTable {
+    /// Creates a table without columns.
+    pub fn new() -> Self {
+        StatsTable::default()
+    }
+
+    /// Adds a column for `stats`, headed by `label`.
+    pub fn column<L: Into<String>>(mut self, label: L, stats: Stats) -> Self {
+        self.columns.push((label.into(), stats));
+        self
+    }
+}
+
+/// A row of a `StatsTable`, and how to render each of its cells.
+type Row = (&'static str, fn(&Stats) -> String);
+
+const ROWS: [Row; 13] = [
+    ("allocations", |stats| stats.allocations.to_string()),
+    ("deallocations", |stats| stats.deallocations.to_string()),
+    ("reallocations", |stats| stats.reallocations.to_string()),
+    ("failures", |stats| stats.failures.to_string()),
+    ("bytes allocated", |stats| {
+        Bytes(stats.bytes_allocated as f64).to_string()
+    }),
+    ("bytes deallocated", |stats| {
+        Bytes(stats.bytes_deallocated as f64).to_string()
+    }),
+    ("bytes reallocated", |stats| {
+        Bytes(stats.bytes_reallocated as f64).to_string()
+    }),
+    ("net bytes", |stats| Bytes(stats.net_bytes() as f64).to_string()),
+    ("live allocations", |stats| stats.live_allocations.to_string()),
+    ("live bytes", |stats| Bytes(stats.bytes_live as f64).to_string()),
+    ("peak bytes", |stats| Bytes(stats.bytes_peak as f64).to_string()),
+    ("average allocation", |stats| {
+        Bytes(stats.average_allocation_size()).to_string()
+    }),
+    ("reallocation ratio", |stats| {
+        format!("{:.2}", stats.reallocation_ratio())
+    }),
+];
+
+impl fmt::Display for StatsTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells: Vec<Vec<String>> = ROWS
+            .iter()
+            .map(|&(_, render)| self.columns.iter().map(|(_, stats)| render(stats)).collect())
+            .collect();
+        let label_width = ROWS.iter().map(|&(name, _)| name.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(column, (label, _))| {
+                cells
+                    .iter()
+                    .map(|row| row[column].chars().count())
+                    .chain(Some(label.chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        write!(f, "{:width$}", "", width = label_width)?;
+        for ((label, _), &width) in self.columns.iter().zip(&widths) {
+            write!(f, "  {:>width$}", label, width = width)?;
+        }
+        for (&(name, _), row) in ROWS.iter().zip(&cells) {
+            write!(f, "\n{:width$}", name, width = label_width)?;
+            for (cell, &width) in row.iter().zip(&widths) {
+                write!(f, "  {:>width$}", cell, width = width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A number of bytes, formatted in binary units.
+pub(crate) struct Bytes(pub(crate) f64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        let bytes = self.0;
+        if bytes.abs() < 1024.0 {
+            return if bytes.fract() == 0.0 {
+                write!(f, "{} B", bytes)
+            } else {
+                write!(f, "{:.1} B", bytes)
+            };
+        }
+        let mut value = bytes / 1024.0;
+        let mut unit = 0;
+        while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    alloc::GlobalAlloc,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use thread;
+use StatsAlloc;
+
+/// A rule deciding which allocation requests fail under fault injection.
+///
+/// Requests are counted from when the fault injection began, starting at
+/// one. Allocations, zeroed allocations and reallocations are all requests;
+/// deallocations never fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Fails only the `n`th request.
+    Nth(usize),
+    /// Fails every `n`th request.
+    EveryNth(usize),
+    /// Fails every request for more than the given number of bytes. For
+    /// reallocations, the new size is compared.
+    LargerThan(usize),
+    /// Fails a pseudo-random fraction of requests, given as a probability
+    /// between `0.0` and `1.0`. The same seed always fails the same requests
+    /// when requests are made in the same order.
+    Random {
+        /// Probability that any one request fails
+        probability: f64,
+        /// Seed for the pseudo-random sequence
+        seed: u64,
+    },
+}
+
+const OFF: usize = 0;
+const NTH: usize = 1;
+const EVERY_NTH: usize = 2;
+const LARGER_THAN: usize = 3;
+const RANDOM: usize = 4;
+
+/// The fault injection settings of a `StatsAlloc`, as saved by a
+/// `FaultInjection` to be restored when it is dropped.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Settings {
+    mode: usize,
+    parameter: usize,
+    threshold: u64,
+    state: u64,
+    requests: usize,
+    thread: usize,
+}
+
+/// Fault injection state of a `StatsAlloc`.
+#[derive(Debug)]
+pub(crate) struct Faults {
+    mode: AtomicUsize,
+    parameter: AtomicUsize,
+    threshold: AtomicU64,
+    state: AtomicU64,
+    requests: AtomicUsize,
+    /// Thread to which injection is limited, or zero for every thread.
+    thread: AtomicUsize,
+}
+
+impl Faults {
+    pub(crate) const fn new() -> Self {
+        Faults {
+            mode: AtomicUsize::new(OFF),
+            parameter: AtomicUsize::new(0),
+            threshold: AtomicU64::new(0),
+            state: AtomicU64::new(0),
+            requests: AtomicUsize::new(0),
+            thread: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts injecting `fault`, returning the settings it replaces.
+    fn start(&self, fault: Fault, thread: usize) -> Settings {
+        let previous = self.settings();
+        self.mode.store(OFF, Ordering::SeqCst);
+        let mode = match fault {
+            Fault::Nth(n) => {
+                self.parameter.store(n, Ordering::SeqCst);
+                NTH
+            },
+            Fault::EveryNth(n) => {
+                self.parameter.store(n, Ordering::SeqCst);
+                EVERY_NTH
+            },
+            Fault::LargerThan(size) => {
+                self.parameter.store(size, Ordering::SeqCst);
+                LARGER_THAN
+            },
+            Fault::Random { probability, seed } => {
+                let threshold = (probability.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+                self.threshold.store(threshold, Ordering::SeqCst);
+                // xorshift never leaves zero, so avoid it as a seed
+                self.state.store(seed | 1, Ordering::SeqCst);
+                RANDOM
+            },
+        };
+        self.requests.store(0, Ordering::SeqCst);
+        self.thread.store(thread, Ordering::SeqCst);
+        self.mode.store(mode, Ordering::SeqCst);
+        previous
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            mode: self.mode.load(Ordering::SeqCst),
+            parameter: self.parameter.load(Ordering::SeqCst),
+            threshold: self.threshold.load(Ordering::SeqCst),
+            state: self.state.load(Ordering::SeqCst),
+            requests: self.requests.load(Ordering::SeqCst),
+            thread: self.thread.load(Ordering::SeqCst),
+        }
+    }
+
+    fn restore(&self, settings: Settings) {
+        self.mode.store(OFF, Ordering::SeqCst);
+        self.parameter.store(settings.parameter, Ordering::SeqCst);
+        self.threshold.store(settings.threshold, Ordering::SeqCst);
+        self.state.store(settings.state, Ordering::SeqCst);
+        self.requests.store(settings.requests, Ordering::SeqCst);
+        self.thread.store(settings.thread, Ordering::SeqCst);
+        self.mode.store(settings.mode, Ordering::SeqCst);
+    }
+
+    /// Decides whether a request for `size` bytes should fail.
+    #[inline]
+    pub(crate) fn should_fail(&self, size: usize) -> bool {
+        let mode = self.mode.load(Ordering::Acquire);
+        if mode == OFF {
+            return false;
+        }
+        let thread = self.thread.load(Ordering::SeqCst);
+        if thread != 0 && thread != thread::id() {
+            return false;
+        }
+        let request = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        let parameter = self.parameter.load(Ordering::SeqCst);
+        match mode {
+            NTH => request == parameter,
+            EVERY_NTH => parameter != 0 && request % parameter == 0,
+            LARGER_THAN => size > parameter,
+            RANDOM => self.next_random() < self.threshold.load(Ordering::SeqCst),
+            _ => false,
+        }
+    }
+
+    fn next_random(&self) -> u64 {
+        let mut state = self.state.load(Ordering::SeqCst);
+        loop {
+            let mut next = state;
+            next ^= next << 13;
+            next ^= next >> 7;
+            next ^= next << 17;
+            match self
+                .state
+                .compare_exchange_weak(state, next, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return next,
+                Err(actual) => state = actual,
+            }
+        }
+    }
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults::new()
+    }
+}
+
+/// Fails allocation requests to a `StatsAlloc` while it is alive.
+///
+/// Failed requests return null without reaching the underlying allocator,
+/// and are counted in `Stats::failures`.
+///
+/// Only one fault injection per allocator is active at a time. Starting
+/// another suspends the current one until the new guard is dropped, when the
+/// previous injection resumes where it left off, counting requests from
+/// where it stopped. Guards are expected to be dropped in the reverse order
+/// of their creation, as they are when scoped.
+///
+/// ```
+/// # use stats_alloc::{Fault, StatsAlloc};
+/// # use std::alloc::{GlobalAlloc, Layout, System};
+/// let alloc = StatsAlloc::new(System);
+/// let layout = Layout::from_size_align(16, 8).unwrap();
+/// let faults = alloc.inject_faults(Fault::Nth(2));
+/// unsafe {
+///     let first = alloc.alloc(layout);
+///     assert!(!first.is_null());
+///     assert!(alloc.alloc(layout).is_null());
+///     alloc.dealloc(first, layout);
+/// }
+/// drop(faults);
+/// assert_eq!(alloc.stats().failures, 1);
+/// ```
+#[derive(Debug)]
+#[must_use = "fault injection stops when the guard is dropped"]
+pub struct FaultInjection<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    alloc: &'a StatsAlloc<T, O>,
+    previous: Settings,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> FaultInjection<'a, T, O> {
+    pub(crate) fn new(alloc: &'a StatsAlloc<T, O>, fault: Fault, current_thread_only: bool) -> Self {
+        let thread = if current_thread_only { thread::id() } else { 0 };
+        let previous = alloc.faults.start(fault, thread);
+        FaultInjection { alloc, previous }
+    }
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Drop for FaultInjection<'a, T, O> {
+    fn drop(&mut self) {
+        self.alloc.faults.restore(self.previous);
+    }
+}
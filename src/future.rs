@@ -0,0 +1,92 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use thread::thread_stats;
+use Stats;
+
+/// Counts the allocations made while a future is polled, on whichever thread
+/// polls it, and completes with its output and the statistics.
+///
+/// **Allocations made through every instrumented allocator are counted**, as
+/// they are through the statistics of the polling thread, to which every
+/// `StatsAlloc` records. Only the work done within `poll` is counted, and not
+/// that of tasks the future spawns, or of other threads.
+///
+/// `bytes_peak` is the highest `bytes_live` of the future at the end of any
+/// of its polls, as growth within a poll cannot be told apart from that of
+/// the thread.
+///
+/// ```
+/// # use stats_alloc::{InstrumentedFuture, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// # use std::future::{poll_fn, Future};
+/// # use std::pin::pin;
+/// # use std::sync::Arc;
+/// # use std::task::{Context, Poll, Wake, Waker};
+/// # struct Noop;
+/// # impl Wake for Noop {
+/// #     fn wake(self: Arc<Self>) {}
+/// # }
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// let waker = Waker::from(Arc::new(Noop));
+/// let mut operation = pin!(InstrumentedFuture::new(poll_fn(|_| {
+///     Poll::Ready(Vec::<u8>::with_capacity(64))
+/// })));
+/// let mut cx = Context::from_waker(&waker);
+/// if let Poll::Ready((v, stats)) = operation.as_mut().poll(&mut cx) {
+///     assert_eq!(stats.bytes_allocated, 64);
+///     # drop(v);
+/// }
+/// ```
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct InstrumentedFuture<F> {
+    future: F,
+    stats: Stats,
+}
+
+impl<F> InstrumentedFuture<F> {
+    /// Wraps `future` to count its allocations.
+    pub fn new(future: F) -> Self {
+        InstrumentedFuture {
+            future,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Returns the statistics of the polls made so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Returns the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F: Future> Future for InstrumentedFuture<F> {
+    type Output = (F::Output, Stats);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: the future is pinned along with `self`, and never moved out
+        // of it while pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let before = thread_stats();
+        let poll = future.poll(cx);
+        let change = thread_stats().saturating_sub(before);
+
+        let peak = this.stats.bytes_peak;
+        this.stats += change;
+        this.stats.bytes_peak = peak.max(this.stats.bytes_live.max(0) as u64);
+        match poll {
+            Poll::Ready(output) => Poll::Ready((output, this.stats)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use counters::{shard_index, ORDERING, SHARDS};
+#[cfg(feature = "serde")]
+use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, ops, sync::atomic::AtomicU64};
+
+/// Number of buckets in a `Histogram`, one per power of two representable by
+/// a `usize`.
+pub const BUCKETS: usize = usize::BITS as usize;
+
+/// A histogram with power-of-two buckets
+///
+/// Bucket `0` counts values of zero and bucket `i` counts values in the range
+/// `2^(i - 1) ..= 2^i - 1`. Values too large for the last bucket are counted
+/// in the last bucket.
+///
+/// With the `serde` feature, a histogram serializes as the sequence of its
+/// bucket counts, leaving out trailing empty buckets.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+}
+
+impl Histogram {
+    /// Creates an empty histogram.
+    #[inline]
+    pub const fn new() -> Self {
+        Histogram { buckets: [0; BUCKETS] }
+    }
+
+    /// Returns the index of the bucket which counts `value`.
+    #[inline]
+    pub fn bucket_of(value: usize) -> usize {
+        let bucket = (usize::BITS - value.leading_zeros()) as usize;
+        bucket.min(BUCKETS - 1)
+    }
+
+    /// Returns the smallest and largest values counted by the given bucket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is not less than `BUCKETS`.
+    pub fn bucket_range(bucket: usize) -> (usize, usize) {
+        assert!(bucket < BUCKETS, "bucket index out of range");
+        match bucket {
+            0 => (0, 0),
+            b if b == BUCKETS - 1 => (1 << (b - 1), usize::MAX),
+            b => (1 << (b - 1), (1 << b) - 1),
+        }
+    }
+
+    /// Returns the counts of each bucket.
+    #[inline]
+    pub fn buckets(&self) -> &[u64; BUCKETS] {
+        &self.buckets
+    }
+
+    /// Returns the total count across all buckets.
+    pub fn total(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns an upper bound for the given percentile, where `percentile` is
+    /// in the range `0.0 ..= 100.0`.
+    ///
+    /// The result is the largest value counted by the bucket in which the
+    /// percentile falls, so it is accurate to within a factor of two. An
+    /// empty histogram reports `0`.
+    ///
+    /// ```
+    /// # use stats_alloc::Histogram;
+    /// let mut sizes = Histogram::default();
+    /// sizes.record(24);
+    /// sizes.record(24);
+    /// sizes.record(4_000);
+    /// assert_eq!(sizes.percentile(50.0), 31);
+    /// assert_eq!(sizes.percentile(99.0), 4_095);
+    /// ```
+    pub fn percentile(&self, percentile: f64) -> usize {
+        let total = self.total();
+        if total == 0 {
+            return 0;
+        }
+        let percentile = percentile.clamp(0.0, 100.0);
+        let rank = ((percentile / 100.0 * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::bucket_range(bucket).1;
+            }
+        }
+        usize::MAX
+    }
+
+    /// Counts `value` in its bucket.
+    #[inline]
+    pub fn record(&mut self, value: usize) {
+        self.buckets[Self::bucket_of(value)] += 1;
+    }
+
+    /// Subtracts the counts of `rhs`, returning `None` if any bucket of
+    /// `rhs` has the greater count.
+    pub fn checked_sub(mut self, rhs: Self) -> Option<Self> {
+        for (lhs, rhs) in self.buckets.iter_mut().zip(rhs.buckets.iter()) {
+            *lhs = lhs.checked_sub(*rhs)?;
+        }
+        Some(self)
+    }
+
+    /// Subtracts the counts of `rhs`, stopping each bucket at zero.
+    pub fn saturating_sub(mut self, rhs: Self) -> Self {
+        for (lhs, rhs) in self.buckets.iter_mut().zip(rhs.buckets.iter()) {
+            *lhs = lhs.saturating_sub(*rhs);
+        }
+        self
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            if count != 0 {
+                let (low, high) = Self::bucket_range(bucket);
+                map.entry(&format_args!("{}..={}", low, high), &count);
+            }
+        }
+        map.finish()
+    }
+}
+
+impl ops::Add for Histogram {
+    type Output = Histogram;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+/// Adds the counts of `rhs`, wrapping around rather than overflowing.
+impl ops::AddAssign for Histogram {
+    fn add_assign(&mut self, rhs: Self) {
+        for (lhs, rhs) in self.buckets.iter_mut().zip(rhs.buckets.iter()) {
+            *lhs = lhs.wrapping_add(*rhs);
+        }
+    }
+}
+
+impl ops::Sub for Histogram {
+    type Output = Histogram;
+
+    fn sub(mut self, rhs: Self) -> Self::Output {
+        self -= rhs;
+        self
+    }
+}
+
+/// Subtracts the counts of `rhs`, wrapping around in any bucket where those
+/// of `rhs` are the greater. Use `checked_sub` or `saturating_sub` to tell.
+impl ops::SubAssign for Histogram {
+    fn sub_assign(&mut self, rhs: Self) {
+        for (lhs, rhs) in self.buckets.iter_mut().zip(rhs.buckets.iter()) {
+            *lhs = lhs.wrapping_sub(*rhs);
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Histogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = self
+            .buckets
+            .iter()
+            .rposition(|&count| count != 0)
+            .map_or(0, |last| last + 1);
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        for count in &self.buckets[..len] {
+            seq.serialize_element(count)?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Histogram {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let counts = Vec::<u64>::deserialize(deserializer)?;
+        if counts.len() > BUCKETS {
+            return Err(de::Error::invalid_length(counts.len(), &"at most one count per bucket"));
+        }
+        let mut histogram = Histogram::new();
+        histogram.buckets[..counts.len()].copy_from_slice(&counts);
+        Ok(histogram)
+    }
+}
+
+/// The atomic counterpart of `Histogram`.
+#[derive(Debug)]
+#[repr(align(128))]
+pub(crate) struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl AtomicHistogram {
+    pub(crate) const fn new() -> Self {
+        AtomicHistogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&self, value: usize) {
+        self.buckets[Histogram::bucket_of(value)].fetch_add(1, ORDERING);
+    }
+
+    /// Adds the current counts to those of `histogram`.
+    pub(crate) fn add_to(&self, histogram: &mut Histogram) {
+        for (count, bucket) in histogram.buckets.iter_mut().zip(self.buckets.iter()) {
+            *count = count.wrapping_add(bucket.load(ORDERING));
+        }
+    }
+
+    /// Adds the current counts to those of `histogram`, setting them to zero.
+    pub(crate) fn take_into(&self, histogram: &mut Histogram) {
+        for (count, bucket) in histogram.buckets.iter_mut().zip(self.buckets.iter()) {
+            *count = count.wrapping_add(bucket.swap(0, ORDERING));
+        }
+    }
+}
+
+impl Default for AtomicHistogram {
+    fn default() -> Self {
+        AtomicHistogram::new()
+    }
+}
+
+/// The histogram of allocation sizes kept by a `StatsAlloc`, spread across
+/// shards as its counters are.
+#[derive(Debug, Default)]
+pub(crate) struct ShardedHistogram {
+    shards: [AtomicHistogram; SHARDS],
+}
+
+impl ShardedHistogram {
+    pub(crate) const fn new() -> Self {
+        ShardedHistogram {
+            shards: [const { AtomicHistogram::new() }; SHARDS],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&self, value: usize) {
+        self.shards[shard_index()].record(value);
+    }
+
+    pub(crate) fn load(&self) -> Histogram {
+        let mut histogram = Histogram::new();
+        for shard in &self.shards {
+            shard.add_to(&mut histogram);
+        }
+        histogram
+    }
+
+    /// Sets the counts to zero, returning their previous values.
+    pub(crate) fn take(&self) -> Histogram {
+        let mut histogram = Histogram::new();
+        for shard in &self.shards {
+            shard.take_into(&mut histogram);
+        }
+        histogram
+    }
+}
@@ -0,0 +1,117 @@
+use thread::{self, thread_stats};
+use tracing::{span, Level, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use Stats;
+
+/// With the `tracing` feature, a `tracing_subscriber` layer which counts the
+/// allocations made while each span is entered, and reports them in an event
+/// when the span closes.
+///
+/// **Allocations made through every instrumented allocator are counted**, as
+/// they are through the statistics of the thread which entered the span, to
+/// which every `StatsAlloc` records. A span counts the allocations of the
+/// spans entered within it. Creating a span, and tracing itself, may allocate
+/// too.
+///
+/// The event is at the `INFO` level with the target `stats_alloc`, and
+/// belongs to the parent of the closed span. It records the `span` name,
+/// `allocations`, `deallocations`, `reallocations`, `bytes_allocated`,
+/// `bytes_deallocated` and `bytes_live`.
+///
+/// ```
+/// # extern crate stats_alloc;
+/// # extern crate tracing;
+/// # extern crate tracing_subscriber;
+/// # use stats_alloc::{AllocLayer, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// # fn main() {
+/// let subscriber = tracing_subscriber::registry().with(AllocLayer::new());
+/// tracing::subscriber::with_default(subscriber, || {
+///     let _span = tracing::info_span!("request").entered();
+///     // ...
+/// });
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocLayer {
+    _private: (),
+}
+
+impl AllocLayer {
+    /// Creates a layer reporting the allocations of every span.
+    pub fn new() -> Self {
+        AllocLayer::default()
+    }
+}
+
+/// The allocations of a span, kept in its extensions.
+#[derive(Debug, Default)]
+struct SpanAllocations {
+    /// Statistics of each thread which has entered the span and not yet
+    /// exited it, as they were when entering.
+    entered: Vec<(usize, Stats)>,
+    stats: Stats,
+}
+
+impl<S> Layer<S> for AllocLayer
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanAllocations::default());
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(allocations) = span.extensions_mut().get_mut::<SpanAllocations>() {
+                // Make room first, so that the span is not charged for it
+                allocations.entered.reserve(1);
+                allocations.entered.push((thread::id(), thread_stats()));
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let latest = thread_stats();
+        if let Some(span) = ctx.span(id) {
+            if let Some(allocations) = span.extensions_mut().get_mut::<SpanAllocations>() {
+                let thread = thread::id();
+                if let Some(position) = allocations.entered.iter().rposition(|&(id, _)| id == thread) {
+                    let (_, entered) = allocations.entered.remove(position);
+                    allocations.stats += latest.saturating_sub(entered);
+                }
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let stats = match span.extensions().get::<SpanAllocations>() {
+            Some(allocations) => allocations.stats,
+            None => return,
+        };
+        let parent = span.parent().map(|parent| parent.id());
+        tracing::event!(
+            target: "stats_alloc",
+            parent: parent,
+            Level::INFO,
+            span = span.name(),
+            allocations = stats.allocations,
+            deallocations = stats.deallocations,
+            reallocations = stats.reallocations,
+            bytes_allocated = stats.bytes_allocated,
+            bytes_deallocated = stats.bytes_deallocated,
+            bytes_live = stats.bytes_live,
+            "span closed"
+        );
+    }
+}
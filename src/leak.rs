@@ -0,0 +1,299 @@
+#[cfg(feature = "heap-profiler")]
+use profiler::{self, Symbol};
+#[cfg(feature = "heap-profiler")]
+use std::cmp::Reverse;
+#[cfg(feature = "heap-profiler")]
+use std::time::Instant;
+use std::{
+    alloc::GlobalAlloc,
+    fmt,
+    fs::File,
+    io::{self, Write},
+    mem,
+    os::raw::c_int,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Mutex, MutexGuard, Once, PoisonError},
+    thread,
+};
+use Stats;
+use StatsAlloc;
+
+/// Checks for memory leaked between its creation and the end of the program,
+/// or of a scope.
+///
+/// A check compares the bytes allocated since it was created with those
+/// deallocated, and writes a `LeakReport` to standard error or to a file.
+/// With the `heap-profiler` feature and the allocator's profiler running,
+/// the report also lists the outstanding tracked allocations.
+///
+/// Installed with `install`, the check reports when the process exits,
+/// whether `main` returns or `std::process::exit` is called. Leaking more
+/// than the threshold set with `fail_above` then makes the process exit with
+/// a failing status, so that continuous integration can catch leaks. Nothing
+/// is reported if the process aborts, as it does on a panic with
+/// `panic = "abort"`.
+///
+/// ```
+/// # use stats_alloc::{LeakCheck, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// fn main() {
+///     LeakCheck::new(GLOBAL).fail_above(64 * 1024).install();
+///     // ...
+/// }
+/// ```
+///
+/// A check which is not installed is a scope guard, which writes its report
+/// when dropped, unless the thread is panicking, or when `finish` is called.
+/// It never ends the process itself: `finish` returns the report, so that the
+/// caller may choose how to exit.
+///
+/// Memory which the standard library allocates lazily and keeps until the
+/// process exits, such as the buffer of standard output, counts as leaked.
+#[derive(Debug)]
+#[must_use = "leaks are reported when the check is dropped"]
+pub struct LeakCheck<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    alloc: &'a StatsAlloc<T, O>,
+    initial_stats: Stats,
+    #[cfg(feature = "heap-profiler")]
+    started: Instant,
+    path: Option<PathBuf>,
+    threshold: Option<u64>,
+    exit_code: i32,
+    #[cfg(feature = "heap-profiler")]
+    backtraces: bool,
+    reported: bool,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> LeakCheck<'a, T, O> {
+    /// Starts checking for leaks from `alloc`, to be reported on standard
+    /// error.
+    pub fn new(alloc: &'a StatsAlloc<T, O>) -> Self {
+        LeakCheck {
+            alloc,
+            initial_stats: alloc.stats(),
+            #[cfg(feature = "heap-profiler")]
+            started: Instant::now(),
+            path: None,
+            threshold: None,
+            exit_code: 1,
+            #[cfg(feature = "heap-profiler")]
+            backtraces: true,
+            reported: false,
+        }
+    }
+
+    /// Writes the report to the file at `path` instead of standard error.
+    pub fn output_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Exits the process with a failing status if more than `bytes` were
+    /// leaked by the time it exits. Only an installed check exits.
+    pub fn fail_above(mut self, bytes: u64) -> Self {
+        self.threshold = Some(bytes);
+        self
+    }
+
+    /// Sets the status with which the process exits when the threshold is
+    /// exceeded, which is 1 by default.
+    pub fn exit_code(mut self, code: i32) -> Self {
+        self.exit_code = code;
+        self
+    }
+
+    /// Sets whether outstanding allocations are grouped by backtrace as well
+    /// as by size, which they are by default.
+    #[cfg(feature = "heap-profiler")]
+    pub fn backtraces(mut self, backtraces: bool) -> Self {
+        self.backtraces = backtraces;
+        self
+    }
+
+    /// Writes the report, and returns it rather than writing it again when
+    /// the check is dropped.
+    pub fn finish(mut self) -> LeakReport {
+        self.reported = true;
+        let report = self.report();
+        if let Err(error) = self.write(&report) {
+            eprintln!("failed to write leak report: {}", error);
+        }
+        report
+    }
+
+    /// Compares the allocations made since the check began with the
+    /// deallocations.
+    pub fn report(&self) -> LeakReport {
+        let change = self.alloc.stats().saturating_sub(self.initial_stats);
+        LeakReport {
+            bytes_allocated: change.bytes_allocated,
+            bytes_deallocated: change.bytes_deallocated,
+            allocations: change.live_allocations,
+            #[cfg(feature = "heap-profiler")]
+            outstanding: self.outstanding(),
+        }
+    }
+
+    #[cfg(feature = "heap-profiler")]
+    fn outstanding(&self) -> Vec<Outstanding> {
+        let mut live = self.alloc.profiler.live(self.started);
+        if !self.backtraces {
+            for &mut (_, ref mut frames) in &mut live {
+                frames.clear();
+            }
+        }
+        live.sort_unstable();
+        let mut outstanding: Vec<Outstanding> = Vec::new();
+        for group in live.chunk_by(|a, b| a == b) {
+            let (size, ref frames) = group[0];
+            outstanding.push(Outstanding {
+                size,
+                allocations: group.len(),
+                backtrace: profiler::resolve(frames),
+            });
+        }
+        outstanding.sort_by_key(|outstanding| Reverse(outstanding.size * outstanding.allocations));
+        outstanding
+    }
+
+    fn write(&self, report: &LeakReport) -> io::Result<()> {
+        match self.path {
+            Some(ref path) => writeln!(File::create(path)?, "{}", report),
+            None => writeln!(io::stderr(), "{}", report),
+        }
+    }
+}
+
+impl<T: GlobalAlloc + Sync + 'static, O: Sync + 'static> LeakCheck<'static, T, O> {
+    /// Reports when the process exits, instead of when the check is dropped,
+    /// exiting with a failing status if more than the threshold was leaked.
+    pub fn install(self) {
+        let check = move || {
+            let (threshold, exit_code) = (self.threshold, self.exit_code);
+            let report = self.finish();
+            if threshold.is_some_and(|threshold| report.leaked_bytes() > threshold) {
+                let _ = io::stdout().flush();
+                // Safety: calling `exit` again from an exit handler is
+                // undefined, so end the process directly instead, skipping
+                // the handlers not yet run.
+                unsafe { _exit(exit_code) }
+            }
+        };
+        installed().push(Box::new(check));
+        INSTALL.call_once(|| {
+            // Safety: `run_installed` may run at any point during exit.
+            if unsafe { atexit(run_installed) } != 0 {
+                eprintln!("failed to install leak check");
+            }
+        });
+    }
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Drop for LeakCheck<'a, T, O> {
+    fn drop(&mut self) {
+        if self.reported || thread::panicking() {
+            return;
+        }
+        let report = self.report();
+        if let Err(error) = self.write(&report) {
+            eprintln!("failed to write leak report: {}", error);
+        }
+    }
+}
+
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> c_int;
+    fn _exit(status: c_int) -> !;
+}
+
+/// Checks to run when the process exits.
+type Installed = Vec<Box<dyn FnOnce() + Send>>;
+
+static INSTALLED: Mutex<Installed> = Mutex::new(Vec::new());
+static INSTALL: Once = Once::new();
+
+fn installed() -> MutexGuard<'static, Installed> {
+    INSTALLED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+extern "C" fn run_installed() {
+    let checks = mem::take(&mut *installed());
+    for check in checks {
+        // Unwinding out of an exit handler would abort
+        let _ = panic::catch_unwind(AssertUnwindSafe(check));
+    }
+}
+
+/// The memory leaked while a `LeakCheck` was alive.
+#[derive(Clone, Debug)]
+#[allow(missing_copy_implementations)]
+pub struct LeakReport {
+    /// Bytes allocated while the check was alive
+    pub bytes_allocated: u64,
+    /// Bytes deallocated while the check was alive
+    pub bytes_deallocated: u64,
+    /// Net number of allocations made while the check was alive
+    pub allocations: i64,
+    #[cfg(feature = "heap-profiler")]
+    outstanding: Vec<Outstanding>,
+}
+
+impl LeakReport {
+    /// Returns the number of bytes allocated but not deallocated.
+    pub fn leaked_bytes(&self) -> u64 {
+        self.bytes_allocated.saturating_sub(self.bytes_deallocated)
+    }
+
+    /// Returns the outstanding allocations made since the check was created
+    /// and tracked by the heap profiler, grouped by size and backtrace, with
+    /// the most bytes first.
+    ///
+    /// Only sampled allocations are tracked, so every allocation is listed
+    /// only while the profiler samples every allocation.
+    #[cfg(feature = "heap-profiler")]
+    pub fn outstanding(&self) -> &[Outstanding] {
+        &self.outstanding
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "leak check: {} bytes in {} allocations outstanding ({} bytes allocated, {} bytes deallocated)",
+            self.leaked_bytes(),
+            self.allocations,
+            self.bytes_allocated,
+            self.bytes_deallocated
+        )?;
+        #[cfg(feature = "heap-profiler")]
+        for outstanding in &self.outstanding {
+            write!(
+                f,
+                "\n\n{} allocations of {} bytes",
+                outstanding.allocations, outstanding.size
+            )?;
+            for symbol in &outstanding.backtrace {
+                write!(f, "\n    {}", symbol)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tracked allocations of one size, and from one backtrace, which were not
+/// deallocated.
+#[cfg(feature = "heap-profiler")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outstanding {
+    /// Size of each allocation
+    pub size: usize,
+    /// Number of allocations
+    pub allocations: usize,
+    /// Backtrace of the allocations, empty unless grouping by backtrace
+    pub backtrace: Vec<Symbol>,
+}
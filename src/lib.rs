@@ -32,37 +32,125 @@
     unused_import_braces,
     unused_imports,
     unused_qualifications,
-    missing_docs
+    unused_must_use,
+    missing_docs,
+    clippy::needless_borrow
 )]
-#![cfg_attr(feature = "nightly", feature(const_fn))]
-#![cfg_attr(feature = "docs-rs", feature(allocator_api))]
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
 
+#[cfg(feature = "heap-profiler")]
+extern crate backtrace;
+#[cfg(feature = "pprof")]
+extern crate flate2;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
+#[cfg(feature = "nightly")]
+mod allocator;
+mod assert;
+mod budget;
+mod counters;
+mod delta;
+#[cfg(feature = "heap-profiler")]
+mod dhat;
+mod display;
+mod fault;
+mod future;
+mod histogram;
+#[cfg(feature = "tracing")]
+mod layer;
+mod leak;
+mod measure;
+mod observer;
+#[cfg(feature = "pprof")]
+mod pprof;
+#[cfg(feature = "heap-profiler")]
+mod profiler;
+mod prometheus;
+mod sampler;
+mod snapshot;
+#[cfg(feature = "tags")]
+mod tag;
+mod thread;
+mod tree;
+
+pub use assert::{AllocGuard, Limits, LimitsExceeded, OnExceeded, Violation};
+pub use delta::StatsDelta;
+#[cfg(feature = "heap-profiler")]
+pub use dhat::DhatOutput;
+pub use display::StatsTable;
+pub use fault::{Fault, FaultInjection};
+pub use future::InstrumentedFuture;
+pub use histogram::{Histogram, BUCKETS};
+#[cfg(feature = "tracing")]
+pub use layer::AllocLayer;
+#[cfg(feature = "heap-profiler")]
+pub use leak::Outstanding;
+pub use leak::{LeakCheck, LeakReport};
+pub use measure::RepeatedStats;
+pub use observer::AllocObserver;
+#[cfg(feature = "heap-profiler")]
+pub use profiler::{HeapProfile, HeapSample, Lifetimes, Symbol};
+pub use prometheus::PrometheusEncoder;
+pub use sampler::{Rates, Sample, Sampler, Series};
+pub use snapshot::Snapshot;
+#[cfg(feature = "tags")]
+pub use tag::{Tag, TagGuard, TagRegion, TAGS};
+pub use thread::{exited_thread_stats, thread_stats, ThreadRegion};
+pub use tree::{NamedRegion, RegionReport, RegionTree, ReportNode};
+
+use budget::Budget;
+use counters::Counters;
+use fault::Faults;
+use histogram::ShardedHistogram;
+#[cfg(feature = "heap-profiler")]
+use profiler::Profiler;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::{
     alloc::{GlobalAlloc, Layout, System},
+    iter,
     ops,
-    sync::atomic::{AtomicIsize, AtomicUsize, Ordering},
+    ptr,
 };
+#[cfg(feature = "tags")]
+use tag::Tags;
 
 /// An instrumenting middleware which keeps track of allocation, deallocation,
 /// and reallocation requests to the underlying global allocator.
+///
+/// Each request is also reported to an `AllocObserver`, which by default is
+/// `()` and ignores them.
 #[derive(Default, Debug)]
-pub struct StatsAlloc<T: GlobalAlloc> {
-    allocations: AtomicUsize,
-    deallocations: AtomicUsize,
-    reallocations: AtomicUsize,
-    bytes_allocated: AtomicUsize,
-    bytes_deallocated: AtomicUsize,
-    bytes_reallocated: AtomicIsize,
+pub struct StatsAlloc<T: GlobalAlloc, O = ()> {
+    counters: Counters,
+    sizes: ShardedHistogram,
+    budget: Budget,
+    faults: Faults,
+    #[cfg(feature = "heap-profiler")]
+    profiler: Profiler,
+    #[cfg(feature = "tags")]
+    tags: Tags,
+    observer: O,
     inner: T,
 }
 
 /// Allocator statistics
+///
+/// With the `serde` feature, `Stats` serialize as a map of their fields by
+/// name. Fields missing when deserializing, such as those added in later
+/// versions, are zero.
 #[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Stats {
     /// Count of allocation operations
-    pub allocations: usize,
+    pub allocations: u64,
     /// Count of deallocation operations
-    pub deallocations: usize,
+    pub deallocations: u64,
     /// Count of reallocation operations
     ///
     /// An example where reallocation may occur: resizing of a `Vec<T>` when
@@ -75,41 +163,73 @@ pub struct Stats {
     /// x.push(0);
     /// x.push(1); // Potential reallocation
     /// ```
-    pub reallocations: usize,
+    pub reallocations: u64,
+    /// Count of `Allocator::grow` and `Allocator::grow_zeroed` operations
+    ///
+    /// These are only made through the `Allocator` API, available with the
+    /// `nightly` feature, and are also counted in `reallocations`.
+    pub grows: u64,
+    /// Count of `Allocator::shrink` operations
+    ///
+    /// These are only made through the `Allocator` API, available with the
+    /// `nightly` feature, and are also counted in `reallocations`.
+    pub shrinks: u64,
     /// Total bytes requested by allocations
-    pub bytes_allocated: usize,
+    pub bytes_allocated: u64,
     /// Total bytes freed by deallocations
-    pub bytes_deallocated: usize,
+    pub bytes_deallocated: u64,
     /// Total of bytes requested minus bytes freed by reallocations
     ///
     /// This number is positive if the total bytes requested by reallocation
     /// operations is greater than the total bytes freed by reallocations. A
     /// positive value indicates that resizable structures are growing, while
     /// a negative value indicates that such structures are shrinking.
-    pub bytes_reallocated: isize,
+    pub bytes_reallocated: i64,
+    /// Count of allocation and reallocation requests which failed
+    ///
+    /// This includes requests refused because of a hard limit or fault
+    /// injection, as well as those failed by the underlying allocator. Failed
+    /// requests are not counted in any other field.
+    pub failures: u64,
+    /// Count of allocations which have not yet been deallocated
+    ///
+    /// In the statistics returned by a `Region` this is the net change, and
+    /// is negative if more allocations were freed than were made.
+    pub live_allocations: i64,
+    /// Bytes currently allocated and not yet deallocated
+    ///
+    /// In the statistics returned by a `Region` this is the net change, and
+    /// is negative if more bytes were freed than were requested.
+    pub bytes_live: i64,
+    /// Highest value reached by `bytes_live`
+    ///
+    /// This is a high-water mark rather than a counter, so subtracting one
+    /// `Stats` from another keeps the peak of the left-hand side, and adding
+    /// them keeps the higher peak. The statistics returned by a `Region`
+    /// instead report the highest `bytes_live` of the allocator seen while
+    /// the region was alive.
+    ///
+    /// With the `sharded-counters` feature the peak is estimated, and may
+    /// fall short of the true peak by up to 64 KiB per shard.
+    pub bytes_peak: u64,
 }
 
 /// An instrumented instance of the system allocator.
-pub static INSTRUMENTED_SYSTEM: StatsAlloc<System> = StatsAlloc {
-    allocations: AtomicUsize::new(0),
-    deallocations: AtomicUsize::new(0),
-    reallocations: AtomicUsize::new(0),
-    bytes_allocated: AtomicUsize::new(0),
-    bytes_deallocated: AtomicUsize::new(0),
-    bytes_reallocated: AtomicIsize::new(0),
-    inner: System,
-};
+pub static INSTRUMENTED_SYSTEM: StatsAlloc<System> = StatsAlloc::system();
 
 impl StatsAlloc<System> {
     /// Provides access to an instrumented instance of the system allocator.
     pub const fn system() -> Self {
         StatsAlloc {
-            allocations: AtomicUsize::new(0),
-            deallocations: AtomicUsize::new(0),
-            reallocations: AtomicUsize::new(0),
-            bytes_allocated: AtomicUsize::new(0),
-            bytes_deallocated: AtomicUsize::new(0),
-            bytes_reallocated: AtomicIsize::new(0),
+            counters: Counters::new(),
+            sizes: ShardedHistogram::new(),
+            budget: Budget::new(),
+            faults: Faults::new(),
+            #[cfg(feature = "heap-profiler")]
+            profiler: Profiler::new(),
+            #[cfg(feature = "tags")]
+            tags: Tags::new(),
+            observer: (),
             inner: System,
         }
     }
@@ -121,12 +241,15 @@ impl<T: GlobalAlloc> StatsAlloc<T> {
     #[cfg(feature = "nightly")]
     pub const fn new(inner: T) -> Self {
         StatsAlloc {
-            allocations: AtomicUsize::new(0),
-            deallocations: AtomicUsize::new(0),
-            reallocations: AtomicUsize::new(0),
-            bytes_allocated: AtomicUsize::new(0),
-            bytes_deallocated: AtomicUsize::new(0),
-            bytes_reallocated: AtomicIsize::new(0),
+            counters: Counters::new(),
+            sizes: ShardedHistogram::new(),
+            budget: Budget::new(),
+            faults: Faults::new(),
+            #[cfg(feature = "heap-profiler")]
+            profiler: Profiler::new(),
+            #[cfg(feature = "tags")]
+            tags: Tags::new(),
+            observer: (),
             inner,
         }
     }
@@ -136,27 +259,480 @@ impl<T: GlobalAlloc> StatsAlloc<T> {
     #[cfg(not(feature = "nightly"))]
     pub fn new(inner: T) -> Self {
         StatsAlloc {
-            allocations: AtomicUsize::new(0),
-            deallocations: AtomicUsize::new(0),
-            reallocations: AtomicUsize::new(0),
-            bytes_allocated: AtomicUsize::new(0),
-            bytes_deallocated: AtomicUsize::new(0),
-            bytes_reallocated: AtomicIsize::new(0),
+            counters: Counters::new(),
+            sizes: ShardedHistogram::new(),
+            budget: Budget::new(),
+            faults: Faults::new(),
+            #[cfg(feature = "heap-profiler")]
+            profiler: Profiler::new(),
+            #[cfg(feature = "tags")]
+            tags: Tags::new(),
+            observer: (),
+            inner,
+        }
+    }
+}
+
+impl<O> StatsAlloc<System, O> {
+    /// Provides access to an instrumented instance of the system allocator
+    /// which reports each request to `observer`.
+    ///
+    /// ```
+    /// # use stats_alloc::{AllocObserver, StatsAlloc};
+    /// # use std::alloc::{Layout, System};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// struct LargeAllocations(AtomicUsize);
+    ///
+    /// impl AllocObserver for LargeAllocations {
+    ///     fn on_alloc(&self, _ptr: *mut u8, layout: Layout) {
+    ///         if layout.size() >= 1 << 20 {
+    ///             self.0.fetch_add(1, Ordering::Relaxed);
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL: StatsAlloc<System, LargeAllocations> =
+    ///     StatsAlloc::system_with_observer(LargeAllocations(AtomicUsize::new(0)));
+    ///
+    /// let buffer: Vec<u8> = Vec::with_capacity(4 << 20);
+    /// assert!(GLOBAL.observer().0.load(Ordering::Relaxed) >= 1);
+    /// # drop(buffer);
+    /// ```
+    pub const fn system_with_observer(observer: O) -> Self {
+        StatsAlloc {
+            counters: Counters::new(),
+            sizes: ShardedHistogram::new(),
+            budget: Budget::new(),
+            faults: Faults::new(),
+            #[cfg(feature = "heap-profiler")]
+            profiler: Profiler::new(),
+            #[cfg(feature = "tags")]
+            tags: Tags::new(),
+            observer,
+            inner: System,
+        }
+    }
+}
+
+impl<T: GlobalAlloc, O> StatsAlloc<T, O> {
+    /// Provides access to an instrumented instance of the given global
+    /// allocator which reports each request to `observer`.
+    #[cfg(feature = "nightly")]
+    pub const fn with_observer(inner: T, observer: O) -> Self {
+        StatsAlloc {
+            counters: Counters::new(),
+            sizes: ShardedHistogram::new(),
+            budget: Budget::new(),
+            faults: Faults::new(),
+            #[cfg(feature = "heap-profiler")]
+            profiler: Profiler::new(),
+            #[cfg(feature = "tags")]
+            tags: Tags::new(),
+            observer,
+            inner,
+        }
+    }
+
+    /// Provides access to an instrumented instance of the given global
+    /// allocator which reports each request to `observer`.
+    #[cfg(not(feature = "nightly"))]
+    pub fn with_observer(inner: T, observer: O) -> Self {
+        StatsAlloc {
+            counters: Counters::new(),
+            sizes: ShardedHistogram::new(),
+            budget: Budget::new(),
+            faults: Faults::new(),
+            #[cfg(feature = "heap-profiler")]
+            profiler: Profiler::new(),
+            #[cfg(feature = "tags")]
+            tags: Tags::new(),
+            observer,
             inner,
         }
     }
 
+    /// Returns the observer to which requests are reported.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
     /// Takes a snapshot of the current view of the allocator statistics.
     pub fn stats(&self) -> Stats {
+        self.counters.load()
+    }
+
+    /// Takes a snapshot of the histogram of the sizes requested by
+    /// allocations and reallocations.
+    ///
+    /// Each allocation counts its size and each reallocation counts its new
+    /// size, so the total is `allocations + reallocations`. The histogram is
+    /// kept apart from `Stats` so that taking statistics stays cheap; use
+    /// `Region::size_histogram()` for the sizes requested within a region.
+    pub fn size_histogram(&self) -> Histogram {
+        self.sizes.load()
+    }
+
+    /// Sets the counters to zero, returning the statistics up to now.
+    ///
+    /// This suits periodic reporters which want the change since their last
+    /// report without keeping a `Region` alive. `bytes_live` and
+    /// `live_allocations` still describe the memory currently allocated, and
+    /// are not reset, while `bytes_peak` starts again from `bytes_live`.
+    ///
+    /// Each counter is swapped with zero atomically, but not all of them at
+    /// once, so a request made at the same time may be split between the
+    /// returned statistics and the next. Statistics of threads are not reset,
+    /// while those of each `Tag` and the histogram of allocation sizes are.
+    ///
+    /// A `Region` alive across a reset reports its change saturated at zero,
+    /// so counts made before the reset which the region would have included
+    /// are lost; reset the region as well to measure from the same point.
+    ///
+    /// ```
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::{GlobalAlloc, Layout, System};
+    /// let alloc = StatsAlloc::new(System);
+    /// let layout = Layout::from_size_align(16, 8).unwrap();
+    /// unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+    /// assert_eq!(alloc.take().allocations, 1);
+    /// assert_eq!(alloc.stats().allocations, 0);
+    /// ```
+    pub fn take(&self) -> Stats {
+        #[cfg(feature = "tags")]
+        self.tags.take();
+        self.sizes.take();
+        self.counters.take()
+    }
+
+    /// Sets the counters to zero, as `take()` does, discarding the previous
+    /// statistics.
+    pub fn reset(&self) {
+        self.take();
+    }
+
+    /// Takes a snapshot of the statistics of the allocations attributed to
+    /// `tag`.
+    ///
+    /// Every allocation is attributed to exactly one tag, so the statistics
+    /// of all tags add up to those of `stats()`, apart from `bytes_peak`.
+    #[cfg(feature = "tags")]
+    pub fn tag_stats(&self, tag: Tag) -> Stats {
+        self.tags.load(tag)
+    }
+
+    /// Returns the hard limit on `bytes_live`, if any.
+    pub fn hard_limit(&self) -> Option<usize> {
+        match self.budget.hard_limit() {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Sets a hard limit on `bytes_live`.
+    ///
+    /// Once set, any allocation or reallocation which would take `bytes_live`
+    /// over the limit fails without reaching the underlying allocator, as if
+    /// the allocator were out of memory. Allocations made before the limit was
+    /// set are unaffected, even if they already exceed it.
+    ///
+    /// ```
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::{GlobalAlloc, Layout, System};
+    /// let alloc = StatsAlloc::new(System);
+    /// alloc.set_hard_limit(1_024);
+    /// let layout = Layout::from_size_align(4_096, 8).unwrap();
+    /// assert!(unsafe { alloc.alloc(layout) }.is_null());
+    /// ```
+    pub fn set_hard_limit(&self, limit: usize) {
+        self.budget.set_hard_limit(limit);
+    }
+
+    /// Removes the hard limit on `bytes_live`.
+    pub fn clear_hard_limit(&self) {
+        self.budget.set_hard_limit(usize::MAX);
+    }
+
+    /// Returns the soft limit on `bytes_live`, if any.
+    pub fn soft_limit(&self) -> Option<usize> {
+        match self.budget.soft_limit() {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Sets a soft limit on `bytes_live`.
+    ///
+    /// The first time an allocation or reallocation takes `bytes_live` over
+    /// the limit, `callback` is invoked with the new `bytes_live`. It is not
+    /// invoked again until the soft limit is set again. With the
+    /// `sharded-counters` feature, `bytes_live` is estimated as for
+    /// `Stats::bytes_peak`, so the callback may come late.
+    ///
+    /// The callback is run from within the allocator, on the thread which
+    /// made the allocation, and before the underlying allocator is called. It
+    /// may allocate, but it must not panic.
+    pub fn set_soft_limit(&self, limit: usize, callback: fn(usize)) {
+        self.budget.set_soft_limit(limit, Some(callback));
+    }
+
+    /// Removes the soft limit on `bytes_live`.
+    pub fn clear_soft_limit(&self) {
+        self.budget.set_soft_limit(usize::MAX, None);
+    }
+
+    /// Starts failing allocation requests from any thread according to
+    /// `fault`, until the returned guard is dropped.
+    pub fn inject_faults(&self, fault: Fault) -> FaultInjection<'_, T, O> {
+        FaultInjection::new(self, fault, false)
+    }
+
+    /// Starts failing allocation requests from the calling thread according
+    /// to `fault`, until the returned guard is dropped.
+    ///
+    /// Requests from other threads, such as those of a test harness, neither
+    /// fail nor count towards `fault`.
+    pub fn inject_thread_faults(&self, fault: Fault) -> FaultInjection<'_, T, O> {
+        FaultInjection::new(self, fault, true)
+    }
+
+    /// Starts sampling allocations for a heap profile, taking the backtrace of
+    /// one allocation for roughly every `sample_interval` bytes allocated.
+    ///
+    /// Sampling is random, so that allocations of every size are represented
+    /// in proportion to the bytes they take, and a `sample_interval` of one
+    /// samples every allocation. Restarting the profiler keeps the samples
+    /// already taken.
+    ///
+    /// ```
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::{GlobalAlloc, Layout, System};
+    /// let alloc = StatsAlloc::new(System);
+    /// // Sample every allocation
+    /// alloc.start_profiling(1);
+    /// let layout = Layout::from_size_align(4 << 20, 8).unwrap();
+    /// let ptr = unsafe { alloc.alloc(layout) };
+    /// let profile = alloc.heap_profile();
+    /// assert_eq!(profile.samples().len(), 1);
+    /// println!("{}", profile);
+    /// # unsafe { alloc.dealloc(ptr, layout) };
+    /// ```
+    #[cfg(feature = "heap-profiler")]
+    pub fn start_profiling(&self, sample_interval: usize) {
+        self.profiler.start(sample_interval, thread::id() as u64);
+    }
+
+    /// Stops sampling allocations, and discards the samples taken.
+    #[cfg(feature = "heap-profiler")]
+    pub fn stop_profiling(&self) {
+        self.profiler.stop(&self.inner);
+    }
+
+    /// Returns the mean number of bytes between samples, if profiling.
+    #[cfg(feature = "heap-profiler")]
+    pub fn sample_interval(&self) -> Option<usize> {
+        self.profiler.interval()
+    }
+
+    /// Returns the lifetimes, in nanoseconds, of the sampled allocations
+    /// which were freed since profiling started.
+    ///
+    /// The counts are complete only with a sample interval of 1. A
+    /// reallocation ends the lifetime of the old allocation. To split the
+    /// allocations made within a region into those freed within it and those
+    /// which outlived it, use `Region::lifetimes()`.
+    #[cfg(feature = "heap-profiler")]
+    pub fn lifetime_histogram(&self) -> Histogram {
+        self.profiler.freed_lifetimes()
+    }
+
+    /// Takes a profile of the sampled allocations which are still live.
+    #[cfg(feature = "heap-profiler")]
+    pub fn heap_profile(&self) -> HeapProfile {
+        self.profiler.profile()
+    }
+
+    /// Decides whether a request for `size` bytes may proceed, reserving
+    /// `growth` bytes of `bytes_live` if so, and returns the new `bytes_live`
+    /// with which to raise the peaks once the request succeeds.
+    #[inline]
+    fn admit(&self, size: usize, growth: usize) -> Option<usize> {
+        if !self.faults.should_fail(size) {
+            if let Some(live) = self.reserve(growth) {
+                return Some(live);
+            }
+        }
+        self.record_failure();
+        None
+    }
+
+    #[inline]
+    fn record_failure(&self) {
+        self.counters.record_failure();
+        thread::record_failure(self.key());
+        #[cfg(feature = "tags")]
+        self.tags.record_failure();
+    }
+
+    /// Allocates from the underlying allocator, with room for the tag of the
+    /// allocation when the `tags` feature is enabled.
+    #[inline]
+    unsafe fn inner_alloc(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        #[cfg(feature = "tags")]
+        return tag::alloc(&self.inner, layout, zeroed);
+        #[cfg(not(feature = "tags"))]
+        if zeroed {
+            self.inner.alloc_zeroed(layout)
+        } else {
+            self.inner.alloc(layout)
+        }
+    }
+
+    #[inline]
+    unsafe fn inner_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "tags")]
+        tag::dealloc(&self.inner, ptr, layout);
+        #[cfg(not(feature = "tags"))]
+        self.inner.dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn inner_realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        #[cfg(feature = "tags")]
+        return tag::realloc(&self.inner, ptr, layout, new_size);
+        #[cfg(not(feature = "tags"))]
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    /// Lets the heap profiler sample a new allocation.
+    #[inline]
+    fn track_alloc(&self, ptr: *mut u8, size: usize) {
+        #[cfg(feature = "heap-profiler")]
+        self.profiler.record_alloc(&self.inner, ptr, size);
+        #[cfg(not(feature = "heap-profiler"))]
+        let _ = (ptr, size);
+    }
+
+    /// Lets the heap profiler forget an allocation, before its memory is
+    /// returned to the underlying allocator.
+    #[inline]
+    fn track_dealloc(&self, ptr: *mut u8) {
+        #[cfg(feature = "heap-profiler")]
+        self.profiler.record_dealloc(ptr);
+        #[cfg(not(feature = "heap-profiler"))]
+        let _ = ptr;
+    }
+
+    /// Reserves `bytes` of `bytes_live`, returning the new `bytes_live`, or
+    /// `None` if doing so would exceed the hard limit.
+    #[inline]
+    fn reserve(&self, bytes: usize) -> Option<usize> {
+        self.counters.reserve(bytes, self.budget.hard_limit())
+    }
+
+    /// Raises the peaks to `live`, the `bytes_live` returned by `admit`, and
+    /// checks it against the soft limit, once the request has succeeded.
+    #[inline]
+    fn raise_peak(&self, live: usize) {
+        self.counters.raise_peak(live);
+        self.budget.check_soft_limit(live);
+    }
+
+    /// Identifies the allocator in the statistics each thread keeps apart
+    /// for a `ThreadRegion`.
+    #[inline]
+    fn key(&self) -> usize {
+        let alloc: *const Self = self;
+        alloc as usize
+    }
+}
+
+impl Stats {
+    /// Subtracts `rhs`, returning `None` if any count would overflow, as it
+    /// does when `rhs` was taken after a reset or from another allocator.
+    ///
+    /// As with `-`, `bytes_peak` is kept from `self`.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        Some(Stats {
+            allocations: self.allocations.checked_sub(rhs.allocations)?,
+            deallocations: self.deallocations.checked_sub(rhs.deallocations)?,
+            reallocations: self.reallocations.checked_sub(rhs.reallocations)?,
+            grows: self.grows.checked_sub(rhs.grows)?,
+            shrinks: self.shrinks.checked_sub(rhs.shrinks)?,
+            bytes_allocated: self.bytes_allocated.checked_sub(rhs.bytes_allocated)?,
+            bytes_deallocated: self.bytes_deallocated.checked_sub(rhs.bytes_deallocated)?,
+            bytes_reallocated: self.bytes_reallocated.checked_sub(rhs.bytes_reallocated)?,
+            failures: self.failures.checked_sub(rhs.failures)?,
+            live_allocations: self.live_allocations.checked_sub(rhs.live_allocations)?,
+            bytes_live: self.bytes_live.checked_sub(rhs.bytes_live)?,
+            bytes_peak: self.bytes_peak,
+        })
+    }
+
+    /// Subtracts `rhs`, stopping each count at zero, or at the bounds of
+    /// `i64` for the signed fields, rather than overflowing.
+    ///
+    /// As with `-`, `bytes_peak` is kept from `self`.
+    pub fn saturating_sub(self, rhs: Self) -> Self {
         Stats {
-            allocations: self.allocations.load(Ordering::SeqCst),
-            deallocations: self.deallocations.load(Ordering::SeqCst),
-            reallocations: self.reallocations.load(Ordering::SeqCst),
-            bytes_allocated: self.bytes_allocated.load(Ordering::SeqCst),
-            bytes_deallocated: self.bytes_deallocated.load(Ordering::SeqCst),
-            bytes_reallocated: self.bytes_reallocated.load(Ordering::SeqCst),
+            allocations: self.allocations.saturating_sub(rhs.allocations),
+            deallocations: self.deallocations.saturating_sub(rhs.deallocations),
+            reallocations: self.reallocations.saturating_sub(rhs.reallocations),
+            grows: self.grows.saturating_sub(rhs.grows),
+            shrinks: self.shrinks.saturating_sub(rhs.shrinks),
+            bytes_allocated: self.bytes_allocated.saturating_sub(rhs.bytes_allocated),
+            bytes_deallocated: self.bytes_deallocated.saturating_sub(rhs.bytes_deallocated),
+            bytes_reallocated: self.bytes_reallocated.saturating_sub(rhs.bytes_reallocated),
+            failures: self.failures.saturating_sub(rhs.failures),
+            live_allocations: self.live_allocations.saturating_sub(rhs.live_allocations),
+            bytes_live: self.bytes_live.saturating_sub(rhs.bytes_live),
+            bytes_peak: self.bytes_peak,
         }
     }
+
+    /// Returns the signed difference between `self` and `rhs`, which may be
+    /// taken in either order.
+    ///
+    /// ```
+    /// # use stats_alloc::Stats;
+    /// let small = Stats { allocations: 2, ..Stats::default() };
+    /// let large = Stats { allocations: 5, ..Stats::default() };
+    /// assert_eq!(small.delta(&large).allocations, -3);
+    /// assert_eq!(large.delta(&small).allocations, 3);
+    /// ```
+    pub fn delta(&self, rhs: &Stats) -> StatsDelta {
+        StatsDelta::from(*self) - StatsDelta::from(*rhs)
+    }
+}
+
+impl ops::Add for Stats {
+    type Output = Stats;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+/// Adds one `Stats` to another, keeping the higher `bytes_peak`.
+///
+/// Counts wrap around rather than overflow, as the counters of the allocator
+/// do.
+impl ops::AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.allocations = self.allocations.wrapping_add(rhs.allocations);
+        self.deallocations = self.deallocations.wrapping_add(rhs.deallocations);
+        self.reallocations = self.reallocations.wrapping_add(rhs.reallocations);
+        self.grows = self.grows.wrapping_add(rhs.grows);
+        self.shrinks = self.shrinks.wrapping_add(rhs.shrinks);
+        self.bytes_allocated = self.bytes_allocated.wrapping_add(rhs.bytes_allocated);
+        self.bytes_deallocated = self.bytes_deallocated.wrapping_add(rhs.bytes_deallocated);
+        self.bytes_reallocated = self.bytes_reallocated.wrapping_add(rhs.bytes_reallocated);
+        self.failures = self.failures.wrapping_add(rhs.failures);
+        self.live_allocations = self.live_allocations.wrapping_add(rhs.live_allocations);
+        self.bytes_live = self.bytes_live.wrapping_add(rhs.bytes_live);
+        self.bytes_peak = self.bytes_peak.max(rhs.bytes_peak);
+    }
 }
 
 impl ops::Sub for Stats {
@@ -168,33 +744,78 @@ impl ops::Sub for Stats {
     }
 }
 
+/// Subtracts one `Stats` from another, keeping `bytes_peak` from the
+/// left-hand side.
+///
+/// Counts wrap around if those of `rhs` are the greater, as they may be when
+/// the allocator was reset in between, so that adding `rhs` back restores
+/// them. Use `checked_sub`, `saturating_sub` or `delta` to tell.
 impl ops::SubAssign for Stats {
     fn sub_assign(&mut self, rhs: Self) {
-        self.allocations -= rhs.allocations;
-        self.deallocations -= rhs.deallocations;
-        self.reallocations -= rhs.reallocations;
-        self.bytes_allocated -= rhs.bytes_allocated;
-        self.bytes_deallocated -= rhs.bytes_deallocated;
-        self.bytes_reallocated -= rhs.bytes_reallocated;
+        self.allocations = self.allocations.wrapping_sub(rhs.allocations);
+        self.deallocations = self.deallocations.wrapping_sub(rhs.deallocations);
+        self.reallocations = self.reallocations.wrapping_sub(rhs.reallocations);
+        self.grows = self.grows.wrapping_sub(rhs.grows);
+        self.shrinks = self.shrinks.wrapping_sub(rhs.shrinks);
+        self.bytes_allocated = self.bytes_allocated.wrapping_sub(rhs.bytes_allocated);
+        self.bytes_deallocated = self.bytes_deallocated.wrapping_sub(rhs.bytes_deallocated);
+        self.bytes_reallocated = self.bytes_reallocated.wrapping_sub(rhs.bytes_reallocated);
+        self.failures = self.failures.wrapping_sub(rhs.failures);
+        self.live_allocations = self.live_allocations.wrapping_sub(rhs.live_allocations);
+        self.bytes_live = self.bytes_live.wrapping_sub(rhs.bytes_live);
+    }
+}
+
+impl iter::Sum for Stats {
+    fn sum<I: Iterator<Item = Stats>>(iter: I) -> Self {
+        iter.fold(Stats::default(), ops::Add::add)
+    }
+}
+
+impl<'a> iter::Sum<&'a Stats> for Stats {
+    fn sum<I: Iterator<Item = &'a Stats>>(iter: I) -> Self {
+        iter.copied().sum()
     }
 }
 
 /// A snapshot of the allocation statistics, which can be used to determine
 /// allocation changes while the `Region` is alive.
+///
+/// A region also tracks the highest `bytes_live` reached by the allocator
+/// while it is alive. Up to 16 regions per allocator can track their peak at
+/// the same time; beyond that, `peak()` falls back to the all-time peak of
+/// the allocator. Likewise, up to 16 regions created while the heap profiler
+/// is running can report `lifetimes()`.
+///
+/// A `Region` includes allocations made by every thread. To measure only the
+/// calling thread, use a `ThreadRegion`.
+///
+/// If the allocator is reset with `StatsAlloc::reset()` or `take()` while the
+/// region is alive, the counts of its change stop at zero rather than
+/// overflowing, and those made before the reset are lost.
 #[derive(Debug)]
-pub struct Region<'a, T: GlobalAlloc + 'a> {
-    alloc: &'a StatsAlloc<T>,
+pub struct Region<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    alloc: &'a StatsAlloc<T, O>,
     initial_stats: Stats,
+    initial_sizes: Histogram,
+    peak_slot: Option<usize>,
+    #[cfg(feature = "heap-profiler")]
+    lifetime_slot: Option<usize>,
 }
 
-impl<'a, T: GlobalAlloc + 'a> Region<'a, T> {
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Region<'a, T, O> {
     /// Creates a new region using statistics from the given instrumented
     /// allocator.
     #[inline]
-    pub fn new(alloc: &'a StatsAlloc<T>) -> Self {
+    pub fn new(alloc: &'a StatsAlloc<T, O>) -> Self {
+        let peak_slot = alloc.counters.claim_peak_slot();
         Region {
             alloc,
             initial_stats: alloc.stats(),
+            initial_sizes: alloc.size_histogram(),
+            peak_slot,
+            #[cfg(feature = "heap-profiler")]
+            lifetime_slot: alloc.profiler.claim_lifetime_slot(),
         }
     }
 
@@ -208,7 +829,9 @@ impl<'a, T: GlobalAlloc + 'a> Region<'a, T> {
     /// those provided by `initial()`.
     #[inline]
     pub fn change(&self) -> Stats {
-        self.alloc.stats() - self.initial_stats
+        let mut diff = self.alloc.stats().saturating_sub(self.initial_stats);
+        diff.bytes_peak = self.peak();
+        diff
     }
 
     /// Returns the difference between the currently reported statistics and
@@ -217,8 +840,11 @@ impl<'a, T: GlobalAlloc + 'a> Region<'a, T> {
     #[inline]
     pub fn change_and_reset(&mut self) -> Stats {
         let latest = self.alloc.stats();
-        let diff = latest - self.initial_stats;
+        let mut diff = latest.saturating_sub(self.initial_stats);
+        diff.bytes_peak = self.peak();
+        self.reset_peak();
         self.initial_stats = latest;
+        self.initial_sizes = self.alloc.size_histogram();
         diff
     }
 
@@ -226,11 +852,90 @@ impl<'a, T: GlobalAlloc + 'a> Region<'a, T> {
     /// referenced allocator.
     #[inline]
     pub fn reset(&mut self) {
+        self.reset_peak();
         self.initial_stats = self.alloc.stats();
+        self.initial_sizes = self.alloc.size_histogram();
+    }
+
+    /// Returns the highest `bytes_live` reported by the allocator since
+    /// instantiation or the last reset.
+    ///
+    /// Subtract `initial().bytes_live` to get the peak growth caused while
+    /// the region was alive.
+    #[inline]
+    pub fn peak(&self) -> u64 {
+        self.alloc.counters.peak(self.peak_slot) as u64
+    }
+
+    /// Returns the histogram of the sizes requested since instantiation or
+    /// the last reset.
+    ///
+    /// ```
+    /// # use stats_alloc::{Region, StatsAlloc};
+    /// # use std::alloc::System;
+    /// # let alloc = StatsAlloc::new(System);
+    /// let reg = Region::new(&alloc);
+    /// // ...
+    /// let sizes = reg.size_histogram();
+    /// println!("p50: {} bytes, p99: {} bytes", sizes.percentile(50.0), sizes.percentile(99.0));
+    /// ```
+    pub fn size_histogram(&self) -> Histogram {
+        self.alloc.size_histogram().saturating_sub(self.initial_sizes)
+    }
+
+    /// Returns the lifetimes of the tracked allocations made since
+    /// instantiation or the last reset, split into those freed within the
+    /// region and those which outlived it.
+    ///
+    /// Returns `None` unless the heap profiler was running when the region
+    /// was created, and fewer than 16 other regions were tracking lifetimes.
+    ///
+    /// ```
+    /// # use stats_alloc::{Region, StatsAlloc};
+    /// # use std::alloc::System;
+    /// let alloc = StatsAlloc::new(System);
+    /// alloc.start_profiling(1);
+    /// let reg = Region::new(&alloc);
+    /// // ...
+    /// let lifetimes = reg.lifetimes().unwrap();
+    /// println!("{} freed, {} outlived", lifetimes.freed.total(), lifetimes.outlived.total());
+    /// ```
+    #[cfg(feature = "heap-profiler")]
+    pub fn lifetimes(&self) -> Option<Lifetimes> {
+        self.lifetime_slot.map(|slot| self.alloc.profiler.lifetimes(slot))
+    }
+
+    fn reset_peak(&self) {
+        if let Some(slot) = self.peak_slot {
+            self.alloc.counters.reset_peak_slot(slot);
+        }
+        #[cfg(feature = "heap-profiler")]
+        if let Some(slot) = self.lifetime_slot {
+            self.alloc.profiler.reset_lifetime_slot(slot);
+        }
     }
 }
 
-unsafe impl<'a, T: GlobalAlloc + 'a> GlobalAlloc for &'a StatsAlloc<T> {
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Drop for Region<'a, T, O> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.peak_slot {
+            self.alloc.counters.release_peak_slot(slot);
+        }
+        #[cfg(feature = "heap-profiler")]
+        if let Some(slot) = self.lifetime_slot {
+            self.alloc.profiler.release_lifetime_slot(slot);
+        }
+    }
+}
+
+#[cfg(feature = "heap-profiler")]
+impl<T: GlobalAlloc, O> Drop for StatsAlloc<T, O> {
+    fn drop(&mut self) {
+        self.profiler.stop(&self.inner);
+    }
+}
+
+unsafe impl<'a, T: GlobalAlloc + 'a, O: AllocObserver + 'a> GlobalAlloc for &'a StatsAlloc<T, O> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         (*self).alloc(layout)
     }
@@ -248,36 +953,97 @@ unsafe impl<'a, T: GlobalAlloc + 'a> GlobalAlloc for &'a StatsAlloc<T> {
     }
 }
 
-unsafe impl<T: GlobalAlloc> GlobalAlloc for StatsAlloc<T> {
+unsafe impl<T: GlobalAlloc, O: AllocObserver> GlobalAlloc for StatsAlloc<T, O> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.allocations.fetch_add(1, Ordering::SeqCst);
-        self.bytes_allocated.fetch_add(layout.size(), Ordering::SeqCst);
-        self.inner.alloc(layout)
+        let live = match self.admit(layout.size(), layout.size()) {
+            Some(live) => live,
+            None => {
+                thread::observe(|| self.observer.on_alloc_failure(layout));
+                return ptr::null_mut();
+            },
+        };
+        let ptr = self.inner_alloc(layout, false);
+        if ptr.is_null() {
+            self.counters.release(layout.size());
+            self.record_failure();
+            thread::observe(|| self.observer.on_alloc_failure(layout));
+            return ptr;
+        }
+        self.raise_peak(live);
+        self.counters.record_alloc(layout.size());
+        self.sizes.record(layout.size());
+        thread::record_alloc(self.key(), layout.size());
+        #[cfg(feature = "tags")]
+        self.tags.record_alloc(tag::tag_of(ptr, layout.size()), layout.size());
+        self.track_alloc(ptr, layout.size());
+        thread::observe(|| self.observer.on_alloc(ptr, layout));
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.deallocations.fetch_add(1, Ordering::SeqCst);
-        self.bytes_deallocated.fetch_add(layout.size(), Ordering::SeqCst);
-        self.inner.dealloc(ptr, layout)
+        self.counters.record_dealloc(layout.size());
+        thread::record_dealloc(self.key(), layout.size());
+        thread::observe(|| self.observer.on_dealloc(ptr, layout));
+        #[cfg(feature = "tags")]
+        self.tags.record_dealloc(tag::tag_of(ptr, layout.size()), layout.size());
+        self.track_dealloc(ptr);
+        self.inner_dealloc(ptr, layout)
     }
 
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        self.allocations.fetch_add(1, Ordering::SeqCst);
-        self.bytes_allocated.fetch_add(layout.size(), Ordering::SeqCst);
-        self.inner.alloc_zeroed(layout)
+        let live = match self.admit(layout.size(), layout.size()) {
+            Some(live) => live,
+            None => {
+                thread::observe(|| self.observer.on_alloc_failure(layout));
+                return ptr::null_mut();
+            },
+        };
+        let ptr = self.inner_alloc(layout, true);
+        if ptr.is_null() {
+            self.counters.release(layout.size());
+            self.record_failure();
+            thread::observe(|| self.observer.on_alloc_failure(layout));
+            return ptr;
+        }
+        self.raise_peak(live);
+        self.counters.record_alloc(layout.size());
+        self.sizes.record(layout.size());
+        thread::record_alloc(self.key(), layout.size());
+        #[cfg(feature = "tags")]
+        self.tags.record_alloc(tag::tag_of(ptr, layout.size()), layout.size());
+        self.track_alloc(ptr, layout.size());
+        thread::observe(|| self.observer.on_alloc_zeroed(ptr, layout));
+        ptr
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        self.reallocations.fetch_add(1, Ordering::SeqCst);
-        if new_size > layout.size() {
-            let difference = new_size - layout.size();
-            self.bytes_allocated.fetch_add(difference, Ordering::SeqCst);
-        } else if new_size < layout.size() {
-            let difference = layout.size() - new_size;
-            self.bytes_deallocated.fetch_add(difference, Ordering::SeqCst);
+        let growth = new_size.saturating_sub(layout.size());
+        let live = match self.admit(new_size, growth) {
+            Some(live) => live,
+            None => {
+                thread::observe(|| self.observer.on_realloc_failure(ptr, layout, new_size));
+                return ptr::null_mut();
+            },
+        };
+        // A reallocation is profiled as a deallocation followed by an
+        // allocation. Should it fail, the allocation is no longer sampled.
+        self.track_dealloc(ptr);
+        let new_ptr = self.inner_realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            self.counters.release(growth);
+            self.record_failure();
+            thread::observe(|| self.observer.on_realloc_failure(ptr, layout, new_size));
+            return new_ptr;
         }
-        self.bytes_reallocated
-            .fetch_add(new_size.wrapping_sub(layout.size()) as isize, Ordering::SeqCst);
-        self.inner.realloc(ptr, layout, new_size)
+        self.raise_peak(live);
+        self.counters.record_realloc(layout.size(), new_size);
+        self.sizes.record(new_size);
+        thread::record_realloc(self.key(), layout.size(), new_size);
+        #[cfg(feature = "tags")]
+        self.tags
+            .record_realloc(tag::tag_of(new_ptr, new_size), layout.size(), new_size);
+        self.track_alloc(new_ptr, new_size);
+        thread::observe(|| self.observer.on_realloc(ptr, new_ptr, layout, new_size));
+        new_ptr
     }
 }
@@ -0,0 +1,136 @@
+use std::alloc::GlobalAlloc;
+use Region;
+use Stats;
+use StatsAlloc;
+
+impl<T: GlobalAlloc, O> StatsAlloc<T, O> {
+    /// Runs `f`, returning its result and the change in the statistics while
+    /// it ran.
+    ///
+    /// The result is returned rather than dropped, so memory it holds is
+    /// still live in the statistics, and freeing it is not counted.
+    ///
+    /// ```
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::System;
+    /// # let alloc = StatsAlloc::new(System);
+    /// let (v, stats) = alloc.measure(|| Vec::<u8>::with_capacity(1_024));
+    /// # drop(v);
+    /// ```
+    pub fn measure<R, F: FnOnce() -> R>(&self, f: F) -> (R, Stats) {
+        Region::new(self).measure(f)
+    }
+
+    /// Runs `f` for `warmup` iterations which are not measured, then for
+    /// `iterations` which are, and summarizes the change in the statistics
+    /// over each measured iteration.
+    ///
+    /// The result of each iteration is dropped once it has been measured, so
+    /// freeing it is not counted in any iteration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `iterations` is zero.
+    ///
+    /// ```
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::System;
+    /// # let alloc = StatsAlloc::new(System);
+    /// let repeated = alloc.measure_repeated(2, 10, || format!("{}", 42));
+    /// assert_eq!(repeated.iterations, 10);
+    /// println!("median: {}", repeated.median);
+    /// ```
+    pub fn measure_repeated<R, F: FnMut() -> R>(&self, warmup: usize, iterations: usize, mut f: F) -> RepeatedStats {
+        assert!(iterations > 0, "at least one iteration must be measured");
+        for _ in 0..warmup {
+            drop(f());
+        }
+        let mut samples = Vec::with_capacity(iterations);
+        for _ in 0..iterations {
+            let (result, stats) = self.measure(&mut f);
+            samples.push(stats);
+            drop(result);
+        }
+        RepeatedStats {
+            iterations,
+            min: select(&mut samples, Rank::Min),
+            median: select(&mut samples, Rank::Median),
+            max: select(&mut samples, Rank::Max),
+        }
+    }
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Region<'a, T, O> {
+    /// Resets the region, runs `f`, and returns its result and the change in
+    /// the statistics while it ran. The region is left reset to the
+    /// statistics as `f` returned.
+    ///
+    /// ```
+    /// # use stats_alloc::{Region, StatsAlloc};
+    /// # use std::alloc::System;
+    /// # let alloc = StatsAlloc::new(System);
+    /// let mut reg = Region::new(&alloc);
+    /// let (first, change) = reg.measure(|| Vec::<u8>::with_capacity(64));
+    /// let (second, change) = reg.measure(|| Vec::<u8>::with_capacity(128));
+    /// # drop((first, second, change));
+    /// ```
+    pub fn measure<R, F: FnOnce() -> R>(&mut self, f: F) -> (R, Stats) {
+        self.reset();
+        let result = f();
+        (result, self.change_and_reset())
+    }
+}
+
+/// A summary of the change in the statistics over each iteration of
+/// `StatsAlloc::measure_repeated()`.
+///
+/// Each field of `min`, `median` and `max` is summarized on its own, so
+/// they need not all come from the same iteration. With an even number of
+/// iterations, the median is the lower of the two middle values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RepeatedStats {
+    /// Number of iterations measured
+    pub iterations: usize,
+    /// Least change of any iteration
+    pub min: Stats,
+    /// Median change over the iterations
+    pub median: Stats,
+    /// Greatest change of any iteration
+    pub max: Stats,
+}
+
+#[derive(Clone, Copy)]
+enum Rank {
+    Min,
+    Median,
+    Max,
+}
+
+/// Returns the statistics whose every field is the value at `rank` among
+/// those fields of `samples`.
+fn select(samples: &mut [Stats], rank: Rank) -> Stats {
+    Stats {
+        allocations: select_field(samples, rank, |stats| stats.allocations),
+        deallocations: select_field(samples, rank, |stats| stats.deallocations),
+        reallocations: select_field(samples, rank, |stats| stats.reallocations),
+        grows: select_field(samples, rank, |stats| stats.grows),
+        shrinks: select_field(samples, rank, |stats| stats.shrinks),
+        bytes_allocated: select_field(samples, rank, |stats| stats.bytes_allocated),
+        bytes_deallocated: select_field(samples, rank, |stats| stats.bytes_deallocated),
+        bytes_reallocated: select_field(samples, rank, |stats| stats.bytes_reallocated),
+        failures: select_field(samples, rank, |stats| stats.failures),
+        live_allocations: select_field(samples, rank, |stats| stats.live_allocations),
+        bytes_live: select_field(samples, rank, |stats| stats.bytes_live),
+        bytes_peak: select_field(samples, rank, |stats| stats.bytes_peak),
+    }
+}
+
+fn select_field<V: Copy + Ord, F: Fn(&Stats) -> V>(samples: &mut [Stats], rank: Rank, field: F) -> V {
+    samples.sort_unstable_by_key(|stats| field(stats));
+    let index = match rank {
+        Rank::Min => 0,
+        Rank::Median => (samples.len() - 1) / 2,
+        Rank::Max => samples.len() - 1,
+    };
+    field(&samples[index])
+}
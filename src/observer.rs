@@ -0,0 +1,124 @@
+use std::alloc::Layout;
+
+/// Receives each request made to a `StatsAlloc`, after it has been counted.
+///
+/// All methods do nothing by default, so an observer only implements those it
+/// needs. `()` ignores every request, and a pair of observers reports each
+/// request to both.
+///
+/// Observers are called from within the global allocator, on the thread which
+/// made the request, and so must not panic. An observer may allocate: any
+/// allocations it makes are counted as usual, but are not themselves reported
+/// to it, so that it cannot recurse into itself.
+pub trait AllocObserver {
+    /// Called after `layout.size()` bytes have been allocated at `ptr`.
+    #[inline]
+    fn on_alloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+
+    /// Called after `layout.size()` zeroed bytes have been allocated at
+    /// `ptr`.
+    #[inline]
+    fn on_alloc_zeroed(&self, ptr: *mut u8, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+
+    /// Called after the allocation at `ptr` has been resized from
+    /// `layout.size()` to `new_size` bytes, and moved to `new_ptr`.
+    #[inline]
+    fn on_realloc(&self, ptr: *mut u8, new_ptr: *mut u8, layout: Layout, new_size: usize) {
+        let _ = (ptr, new_ptr, layout, new_size);
+    }
+
+    /// Called before the allocation at `ptr` is deallocated.
+    #[inline]
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+
+    /// Called when an allocation of `layout.size()` bytes failed.
+    #[inline]
+    fn on_alloc_failure(&self, layout: Layout) {
+        let _ = layout;
+    }
+
+    /// Called when resizing the allocation at `ptr` from `layout.size()` to
+    /// `new_size` bytes failed. The allocation remains valid.
+    #[inline]
+    fn on_realloc_failure(&self, ptr: *mut u8, layout: Layout, new_size: usize) {
+        let _ = (ptr, layout, new_size);
+    }
+}
+
+impl AllocObserver for () {}
+
+impl<A: AllocObserver, B: AllocObserver> AllocObserver for (A, B) {
+    #[inline]
+    fn on_alloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.on_alloc(ptr, layout);
+        self.1.on_alloc(ptr, layout);
+    }
+
+    #[inline]
+    fn on_alloc_zeroed(&self, ptr: *mut u8, layout: Layout) {
+        self.0.on_alloc_zeroed(ptr, layout);
+        self.1.on_alloc_zeroed(ptr, layout);
+    }
+
+    #[inline]
+    fn on_realloc(&self, ptr: *mut u8, new_ptr: *mut u8, layout: Layout, new_size: usize) {
+        self.0.on_realloc(ptr, new_ptr, layout, new_size);
+        self.1.on_realloc(ptr, new_ptr, layout, new_size);
+    }
+
+    #[inline]
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.on_dealloc(ptr, layout);
+        self.1.on_dealloc(ptr, layout);
+    }
+
+    #[inline]
+    fn on_alloc_failure(&self, layout: Layout) {
+        self.0.on_alloc_failure(layout);
+        self.1.on_alloc_failure(layout);
+    }
+
+    #[inline]
+    fn on_realloc_failure(&self, ptr: *mut u8, layout: Layout, new_size: usize) {
+        self.0.on_realloc_failure(ptr, layout, new_size);
+        self.1.on_realloc_failure(ptr, layout, new_size);
+    }
+}
+
+impl<O: AllocObserver + ?Sized> AllocObserver for &O {
+    #[inline]
+    fn on_alloc(&self, ptr: *mut u8, layout: Layout) {
+        (**self).on_alloc(ptr, layout)
+    }
+
+    #[inline]
+    fn on_alloc_zeroed(&self, ptr: *mut u8, layout: Layout) {
+        (**self).on_alloc_zeroed(ptr, layout)
+    }
+
+    #[inline]
+    fn on_realloc(&self, ptr: *mut u8, new_ptr: *mut u8, layout: Layout, new_size: usize) {
+        (**self).on_realloc(ptr, new_ptr, layout, new_size)
+    }
+
+    #[inline]
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        (**self).on_dealloc(ptr, layout)
+    }
+
+    #[inline]
+    fn on_alloc_failure(&self, layout: Layout) {
+        (**self).on_alloc_failure(layout)
+    }
+
+    #[inline]
+    fn on_realloc_failure(&self, ptr: *mut u8, layout: Layout, new_size: usize) {
+        (**self).on_realloc_failure(ptr, layout, new_size)
+    }
+}
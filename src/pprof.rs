@@ -0,0 +1,214 @@
+use flate2::{write::GzEncoder, Compression};
+use profiler::{HeapProfile, Symbol};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+impl HeapProfile {
+    /// Writes the profile as a gzip-compressed pprof `profile.proto`, which
+    /// `go tool pprof` and other pprof viewers can open.
+    ///
+    /// The profile has the sample types of a Go heap profile: `alloc_objects`
+    /// and `alloc_space` count every allocation made while profiling, and
+    /// `inuse_objects` and `inuse_space` those still live. Backtraces are
+    /// symbolized as they are written.
+    ///
+    /// ```no_run
+    /// # use stats_alloc::StatsAlloc;
+    /// # use std::alloc::System;
+    /// # use std::fs::File;
+    /// let alloc = StatsAlloc::new(System);
+    /// alloc.start_profiling(512 * 1024);
+    /// // ...
+    /// let file = File::create("heap.pb.gz")?;
+    /// alloc.heap_profile().write_pprof(file)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn write_pprof<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        encoder.write_all(&self.to_pprof())?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    fn to_pprof(&self) -> Vec<u8> {
+        let mut builder = Builder::default();
+        let mut profile = Message::default();
+        for &(kind, unit) in &[
+            ("alloc_objects", "count"),
+            ("alloc_space", "bytes"),
+            ("inuse_objects", "count"),
+            ("inuse_space", "bytes"),
+        ] {
+            profile.message(1, &builder.value_type(kind, unit));
+        }
+        for sample in self.samples() {
+            let mut location_ids = Vec::new();
+            let symbols = sample.resolve();
+            for frame in symbols.chunk_by(|a, b| a.address == b.address) {
+                location_ids.push(builder.location(frame));
+            }
+            let values = [
+                sample.total_allocations(),
+                sample.total_bytes(),
+                sample.allocations(),
+                sample.bytes(),
+            ];
+            let mut message = Message::default();
+            message.packed(1, location_ids);
+            message.packed(2, values.iter().map(|&value| value as u64));
+            profile.message(2, &message);
+        }
+        for location in &builder.locations {
+            profile.message(4, location);
+        }
+        for function in &builder.functions {
+            profile.message(5, function);
+        }
+        let period_type = builder.value_type("space", "bytes");
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        for string in &builder.strings {
+            profile.bytes(6, string.as_bytes());
+        }
+        profile.uint(9, time);
+        profile.message(11, &period_type);
+        profile.uint(12, self.sample_interval() as u64);
+        profile.0
+    }
+}
+
+/// The tables of a profile which are shared between samples.
+struct Builder {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u64>,
+    functions: Vec<Message>,
+    function_ids: HashMap<(String, String), u64>,
+    locations: Vec<Message>,
+    location_ids: HashMap<usize, u64>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            // The first string must be empty
+            strings: vec![String::new()],
+            string_ids: Some((String::new(), 0)).into_iter().collect(),
+            functions: Vec::new(),
+            function_ids: HashMap::new(),
+            locations: Vec::new(),
+            location_ids: HashMap::new(),
+        }
+    }
+}
+
+impl Builder {
+    fn string(&mut self, string: &str) -> u64 {
+        if let Some(&id) = self.string_ids.get(string) {
+            return id;
+        }
+        let id = self.strings.len() as u64;
+        self.strings.push(string.to_owned());
+        self.string_ids.insert(string.to_owned(), id);
+        id
+    }
+
+    fn value_type(&mut self, kind: &str, unit: &str) -> Message {
+        let mut message = Message::default();
+        message.uint(1, self.string(kind));
+        message.uint(2, self.string(unit));
+        message
+    }
+
+    /// Returns the id of the location of a frame, given the symbols it
+    /// resolved to, innermost first.
+    fn location(&mut self, symbols: &[Symbol]) -> u64 {
+        let address = symbols[0].address;
+        if let Some(&id) = self.location_ids.get(&address) {
+            return id;
+        }
+        let id = self.locations.len() as u64 + 1;
+        let mut location = Message::default();
+        location.uint(1, id);
+        location.uint(3, address as u64);
+        for symbol in symbols.iter().filter(|symbol| symbol.name.is_some()) {
+            let mut line = Message::default();
+            line.uint(1, self.function(symbol));
+            line.uint(2, u64::from(symbol.line.unwrap_or(0)));
+            location.message(4, &line);
+        }
+        self.locations.push(location);
+        self.location_ids.insert(address, id);
+        id
+    }
+
+    fn function(&mut self, symbol: &Symbol) -> u64 {
+        let name = symbol.name.clone().unwrap_or_default();
+        let file = symbol
+            .file
+            .as_ref()
+            .map(|file| file.display().to_string())
+            .unwrap_or_default();
+        let key = (name, file);
+        if let Some(&id) = self.function_ids.get(&key) {
+            return id;
+        }
+        let id = self.functions.len() as u64 + 1;
+        let mut function = Message::default();
+        function.uint(1, id);
+        function.uint(2, self.string(&key.0));
+        function.uint(3, self.string(&key.0));
+        function.uint(4, self.string(&key.1));
+        self.functions.push(function);
+        self.function_ids.insert(key, id);
+        id
+    }
+}
+
+/// A protocol buffer message being encoded.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    /// Writes an integer field, leaving it out if it is zero.
+    fn uint(&mut self, field: u64, value: u64) {
+        if value != 0 {
+            self.key(field, 0);
+            self.varint(value);
+        }
+    }
+
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn message(&mut self, field: u64, message: &Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed<I: IntoIterator<Item = u64>>(&mut self, field: u64, values: I) {
+        let mut packed = Message::default();
+        for value in values {
+            packed.varint(value);
+        }
+        self.bytes(field, &packed.0);
+    }
+}
@@ -0,0 +1,1005 @@
+use backtrace;
+use histogram::Histogram;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    ffi::c_void,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+    path::{Path, PathBuf},
+    ptr,
+    slice,
+    sync::{
+        atomic::{AtomicIsize, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    time::{Duration, Instant},
+};
+use thread;
+
+/// Deepest backtrace kept for a sampled allocation.
+const MAX_FRAMES: usize = 32;
+
+/// Number of counters in the filter consulted on each deallocation.
+const FILTER_SIZE: usize = 256;
+
+/// Smallest number of slots allocated for a table.
+const MIN_CAPACITY: usize = 64;
+
+/// Number of `Region`s which may track lifetimes at the same time.
+const LIFETIME_SLOTS: usize = 16;
+
+/// An element of a `Table`, for which all zero bytes is an empty slot.
+trait Slot: Copy {
+    fn is_empty(&self) -> bool;
+    fn hash(&self) -> usize;
+}
+
+/// A backtrace captured by the profiler.
+#[derive(Clone, Copy, Debug)]
+struct Frames {
+    depth: usize,
+    frames: [usize; MAX_FRAMES],
+}
+
+impl Frames {
+    fn capture() -> Self {
+        let mut frames = Frames {
+            depth: 0,
+            frames: [0; MAX_FRAMES],
+        };
+        backtrace::trace(|frame| {
+            frames.frames[frames.depth] = frame.ip() as usize;
+            frames.depth += 1;
+            frames.depth < MAX_FRAMES
+        });
+        frames
+    }
+
+    fn as_slice(&self) -> &[usize] {
+        &self.frames[..self.depth]
+    }
+
+    /// Hashes the backtrace, never to zero.
+    fn hash(&self) -> usize {
+        let mut hasher = FnvHasher(0xcbf2_9ce4_8422_2325);
+        self.as_slice().hash(&mut hasher);
+        hasher.finish() as usize | 1
+    }
+}
+
+/// A sampled allocation.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    /// Address of the allocation, or zero for an empty slot.
+    ptr: usize,
+    size: usize,
+    /// Number of allocations the sample stands for.
+    scale: f64,
+    /// Nanoseconds from the first sample to the allocation.
+    time: u64,
+    site: usize,
+    frames: Frames,
+}
+
+impl Entry {
+    /// Returns the number of bytes the sample stands for.
+    fn bytes(&self) -> f64 {
+        self.scale * self.size as f64
+    }
+}
+
+impl Slot for Entry {
+    fn is_empty(&self) -> bool {
+        self.ptr == 0
+    }
+
+    fn hash(&self) -> usize {
+        hash(self.ptr)
+    }
+}
+
+/// The samples taken at one backtrace.
+#[derive(Clone, Copy, Debug)]
+struct Site {
+    /// Hash of the backtrace, or zero for an empty slot.
+    hash: usize,
+    frames: Frames,
+    live: Totals,
+    total: Totals,
+    /// Live allocations and bytes when the site had the most live bytes.
+    max: (f64, f64),
+    /// Live allocations and bytes when the whole heap had the most.
+    at_peak: (f64, f64),
+    /// Sum of the times of the live samples, weighted by scale.
+    live_times: f64,
+    /// Sum of the lifetimes of the freed samples, weighted by scale.
+    lifetimes: f64,
+}
+
+impl Slot for Site {
+    fn is_empty(&self) -> bool {
+        self.hash == 0
+    }
+
+    fn hash(&self) -> usize {
+        self.hash
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+struct Totals {
+    sampled_allocations: usize,
+    sampled_bytes: usize,
+    allocations: f64,
+    bytes: f64,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &Entry) {
+        self.sampled_allocations += 1;
+        self.sampled_bytes += entry.size;
+        self.allocations += entry.scale;
+        self.bytes += entry.bytes();
+    }
+
+    fn remove(&mut self, entry: &Entry) {
+        self.sampled_allocations -= 1;
+        self.sampled_bytes -= entry.size;
+        self.allocations -= entry.scale;
+        self.bytes -= entry.bytes();
+    }
+}
+
+/// An open-addressed hash table with linear probing.
+///
+/// The table is allocated from the allocator being instrumented rather than
+/// through it, so that it is neither counted nor sampled itself.
+#[derive(Debug)]
+struct Table<S> {
+    slots: *mut S,
+    capacity: usize,
+    len: usize,
+}
+
+// Safety: the slots are owned by the table, and only reached through it.
+unsafe impl<S: Send> Send for Table<S> {}
+
+impl<S: Slot> Table<S> {
+    const fn new() -> Self {
+        Table {
+            slots: ptr::null_mut(),
+            capacity: 0,
+            len: 0,
+        }
+    }
+
+    fn slots(&self) -> &[S] {
+        if self.slots.is_null() {
+            return &[];
+        }
+        // Safety: `slots` points to `capacity` initialised slots.
+        unsafe { slice::from_raw_parts(self.slots, self.capacity) }
+    }
+
+    fn slots_mut(&mut self) -> &mut [S] {
+        if self.slots.is_null() {
+            return &mut [];
+        }
+        // Safety: as for `slots`.
+        unsafe { slice::from_raw_parts_mut(self.slots, self.capacity) }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &S> {
+        self.slots().iter().filter(|slot| !slot.is_empty())
+    }
+
+    /// Returns the index of the slot matching `key`, or of the empty slot
+    /// where it would go.
+    fn probe<F: Fn(&S) -> bool>(&self, hash: usize, key: F) -> usize {
+        let mask = self.capacity - 1;
+        let slots = self.slots();
+        let mut index = hash & mask;
+        while !slots[index].is_empty() && !key(&slots[index]) {
+            index = (index + 1) & mask;
+        }
+        index
+    }
+
+    fn get_mut<F: Fn(&S) -> bool>(&mut self, hash: usize, key: F) -> Option<&mut S> {
+        if self.len == 0 {
+            return None;
+        }
+        let index = self.probe(hash, key);
+        Some(&mut self.slots_mut()[index]).filter(|slot| !slot.is_empty())
+    }
+
+    /// Returns the slot matching `key`, first filling an empty slot with
+    /// `value` if there is none. Returns `None` if the table cannot grow.
+    fn get_or_insert<A, F>(&mut self, inner: &A, hash: usize, key: F, value: S) -> Option<&mut S>
+    where
+        A: GlobalAlloc,
+        F: Fn(&S) -> bool,
+    {
+        if (self.len + 1) * 4 > self.capacity * 3 && !self.grow(inner) {
+            return None;
+        }
+        let index = self.probe(hash, key);
+        let slot = &mut self.slots_mut()[index];
+        if slot.is_empty() {
+            *slot = value;
+            self.len += 1;
+        }
+        Some(&mut self.slots_mut()[index])
+    }
+
+    fn remove<F: Fn(&S) -> bool>(&mut self, hash: usize, key: F) -> Option<S> {
+        if self.len == 0 {
+            return None;
+        }
+        let mask = self.capacity - 1;
+        let index = self.probe(hash, key);
+        let slots = self.slots_mut();
+        if slots[index].is_empty() {
+            return None;
+        }
+        let removed = slots[index];
+        // Shift later slots of the same run back into the hole, so that no
+        // lookup stops early at an empty slot.
+        let mut hole = index;
+        let mut next = (index + 1) & mask;
+        while !slots[next].is_empty() {
+            let home = slots[next].hash() & mask;
+            if next.wrapping_sub(home) & mask >= next.wrapping_sub(hole) & mask {
+                slots[hole] = slots[next];
+                hole = next;
+            }
+            next = (next + 1) & mask;
+        }
+        // Safety: all zero bytes is an empty slot.
+        slots[hole] = unsafe { mem::zeroed() };
+        self.len -= 1;
+        Some(removed)
+    }
+
+    fn grow<A: GlobalAlloc>(&mut self, inner: &A) -> bool {
+        let capacity = (self.capacity * 2).max(MIN_CAPACITY);
+        let layout = match Layout::array::<S>(capacity) {
+            Ok(layout) => layout,
+            Err(_) => return false,
+        };
+        // Safety: the layout has a non-zero size, and zeroed slots are empty.
+        let slots = unsafe { inner.alloc_zeroed(layout) } as *mut S;
+        if slots.is_null() {
+            return false;
+        }
+        let old = mem::replace(
+            self,
+            Table {
+                slots,
+                capacity,
+                len: 0,
+            },
+        );
+        for slot in old.iter() {
+            let index = self.probe(slot.hash(), |_| false);
+            self.slots_mut()[index] = *slot;
+            self.len += 1;
+        }
+        old.free(inner);
+        true
+    }
+
+    fn free<A: GlobalAlloc>(self, inner: &A) {
+        if !self.slots.is_null() {
+            let layout = Layout::array::<S>(self.capacity).expect("layout was valid when allocated");
+            // Safety: allocated by `grow` with the same layout.
+            unsafe { inner.dealloc(self.slots as *mut u8, layout) };
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Tables {
+    entries: Table<Entry>,
+    sites: Table<Site>,
+    /// When the first sample was taken.
+    start: Option<Instant>,
+    /// Estimated live bytes of all sites.
+    live: f64,
+    /// Most estimated live bytes so far, and when they were reached.
+    peak: f64,
+    peak_time: u64,
+    /// Lifetimes of every sample freed.
+    freed: Histogram,
+    lifetime_slots: [LifetimeSlot; LIFETIME_SLOTS],
+}
+
+/// The lifetimes tracked for a `Region`.
+#[derive(Clone, Copy, Debug)]
+struct LifetimeSlot {
+    /// When the region began, or `None` if the slot is free.
+    start: Option<u64>,
+    freed: Histogram,
+}
+
+impl LifetimeSlot {
+    const FREE: LifetimeSlot = LifetimeSlot {
+        start: None,
+        freed: Histogram::new(),
+    };
+}
+
+impl Tables {
+    const fn new() -> Self {
+        Tables {
+            entries: Table::new(),
+            sites: Table::new(),
+            start: None,
+            live: 0.0,
+            peak: 0.0,
+            peak_time: 0,
+            freed: Histogram::new(),
+            lifetime_slots: [LifetimeSlot::FREE; LIFETIME_SLOTS],
+        }
+    }
+
+    /// Returns the nanoseconds since the first sample.
+    fn now(&mut self) -> u64 {
+        self.start.get_or_insert_with(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    /// Adds a sample, returning `false` if there was no room for it.
+    fn insert<A: GlobalAlloc>(&mut self, inner: &A, mut entry: Entry) -> bool {
+        // A sample left behind at the same address was freed unseen
+        self.remove(entry.ptr);
+        entry.time = self.now();
+        let empty = Site {
+            hash: entry.site,
+            frames: entry.frames,
+            live: Totals::default(),
+            total: Totals::default(),
+            max: (0.0, 0.0),
+            at_peak: (0.0, 0.0),
+            live_times: 0.0,
+            lifetimes: 0.0,
+        };
+        let frames = entry.frames.as_slice();
+        if self
+            .sites
+            .get_or_insert(inner, entry.site, |site| site.frames.as_slice() == frames, empty)
+            .is_none()
+        {
+            return false;
+        }
+        // The site is only counted once the sample has a place of its own
+        if self
+            .entries
+            .get_or_insert(inner, hash(entry.ptr), |_| false, entry)
+            .is_none()
+        {
+            return false;
+        }
+        let site = self.site_mut(&entry);
+        site.live.add(&entry);
+        site.total.add(&entry);
+        site.live_times += entry.scale * entry.time as f64;
+        if site.live.bytes > site.max.1 {
+            site.max = (site.live.allocations, site.live.bytes);
+        }
+        self.live += entry.bytes();
+        if self.live > self.peak {
+            self.peak = self.live;
+            self.peak_time = entry.time;
+            for site in self.sites.slots_mut().iter_mut().filter(|site| !site.is_empty()) {
+                site.at_peak = (site.live.allocations, site.live.bytes);
+            }
+        }
+        true
+    }
+
+    /// Removes the sample at `ptr`, returning `false` if there was none.
+    fn remove(&mut self, ptr: usize) -> bool {
+        let entry = match self.entries.remove(hash(ptr), |entry| entry.ptr == ptr) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let now = self.now();
+        let lifetime = now.saturating_sub(entry.time);
+        self.live -= entry.bytes();
+        let site = self.site_mut(&entry);
+        site.live.remove(&entry);
+        site.live_times -= entry.scale * entry.time as f64;
+        site.lifetimes += entry.scale * lifetime as f64;
+        self.freed.record(lifetime as usize);
+        for slot in &mut self.lifetime_slots {
+            if slot.start.is_some_and(|start| entry.time >= start) {
+                slot.freed.record(lifetime as usize);
+            }
+        }
+        true
+    }
+
+    fn site_mut(&mut self, entry: &Entry) -> &mut Site {
+        let frames = entry.frames.as_slice();
+        self.sites
+            .get_mut(entry.site, |site| site.frames.as_slice() == frames)
+            .expect("every sample has a site")
+    }
+}
+
+/// The sampling heap profiler of a `StatsAlloc`.
+///
+/// On average one allocation is sampled for every `interval` bytes
+/// allocated, by counting down a randomly drawn number of bytes between
+/// samples. Larger allocations are therefore more likely to be sampled, and
+/// each sample is weighted by the inverse of its probability when estimating
+/// the heap. Live samples are kept by address, and every sample is also
+/// added to the totals of its backtrace.
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    /// Mean bytes between samples, or zero when profiling is off.
+    interval: AtomicUsize,
+    /// Bytes left to allocate before the next sample.
+    countdown: AtomicIsize,
+    random: AtomicU64,
+    /// Count of live samples whose address hashes to each counter, so that
+    /// most deallocations need not lock the tables.
+    filter: [AtomicU32; FILTER_SIZE],
+    tables: Mutex<Tables>,
+}
+
+impl Profiler {
+    pub(crate) const fn new() -> Self {
+        Profiler {
+            interval: AtomicUsize::new(0),
+            countdown: AtomicIsize::new(0),
+            random: AtomicU64::new(0),
+            filter: [const { AtomicU32::new(0) }; FILTER_SIZE],
+            tables: Mutex::new(Tables::new()),
+        }
+    }
+
+    pub(crate) fn start(&self, interval: usize, seed: u64) {
+        let interval = interval.max(1);
+        // xorshift never leaves zero, so avoid it as a seed
+        self.random.store(seed | 1, Ordering::SeqCst);
+        self.countdown.store(self.next_countdown(interval), Ordering::SeqCst);
+        self.interval.store(interval, Ordering::SeqCst);
+    }
+
+    pub(crate) fn stop<A: GlobalAlloc>(&self, inner: &A) {
+        self.interval.store(0, Ordering::SeqCst);
+        let mut tables = self.lock();
+        for counter in &self.filter {
+            counter.store(0, Ordering::SeqCst);
+        }
+        let old = mem::replace(&mut *tables, Tables::new());
+        old.entries.free(inner);
+        old.sites.free(inner);
+        // Regions keep their slots, which now start with the next sample
+        for (slot, old) in tables.lifetime_slots.iter_mut().zip(old.lifetime_slots.iter()) {
+            if old.start.is_some() {
+                slot.start = Some(0);
+            }
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Option<usize> {
+        match self.interval.load(Ordering::Relaxed) {
+            0 => None,
+            interval => Some(interval),
+        }
+    }
+
+    /// Samples the allocation of `size` bytes at `ptr` if its turn has come.
+    #[inline]
+    pub(crate) fn record_alloc<A: GlobalAlloc>(&self, inner: &A, ptr: *mut u8, size: usize) {
+        let interval = self.interval.load(Ordering::Relaxed);
+        if interval == 0 || !self.should_sample(interval, size) {
+            return;
+        }
+        // Capturing a backtrace may itself allocate, which is not sampled.
+        thread::sample(|| {
+            let frames = Frames::capture();
+            let entry = Entry {
+                ptr: ptr as usize,
+                size,
+                scale: scale(size, interval),
+                time: 0,
+                site: frames.hash(),
+                frames,
+            };
+            if self.lock().insert(inner, entry) {
+                self.filter[filter_index(entry.ptr)].fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// Forgets the allocation at `ptr`, if it was sampled, recording its
+    /// lifetime. This must happen before the memory is returned to the
+    /// underlying allocator, which may hand the same address out again.
+    #[inline]
+    pub(crate) fn record_dealloc(&self, ptr: *mut u8) {
+        let ptr = ptr as usize;
+        let counter = &self.filter[filter_index(ptr)];
+        if counter.load(Ordering::Relaxed) != 0 && self.lock().remove(ptr) {
+            counter.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the lifetimes of every sample freed.
+    pub(crate) fn freed_lifetimes(&self) -> Histogram {
+        self.lock().freed
+    }
+
+    /// Reserves a lifetime slot for a `Region`, starting now. Returns `None`
+    /// if profiling is off or every slot is taken.
+    pub(crate) fn claim_lifetime_slot(&self) -> Option<usize> {
+        self.interval()?;
+        let mut tables = self.lock();
+        let now = tables.now();
+        let index = tables.lifetime_slots.iter().position(|slot| slot.start.is_none())?;
+        tables.lifetime_slots[index] = LifetimeSlot {
+            start: Some(now),
+            freed: Histogram::new(),
+        };
+        Some(index)
+    }
+
+    pub(crate) fn reset_lifetime_slot(&self, index: usize) {
+        let mut tables = self.lock();
+        let now = tables.now();
+        tables.lifetime_slots[index] = LifetimeSlot {
+            start: Some(now),
+            freed: Histogram::new(),
+        };
+    }
+
+    pub(crate) fn release_lifetime_slot(&self, index: usize) {
+        self.lock().lifetime_slots[index] = LifetimeSlot::FREE;
+    }
+
+    /// Returns the lifetimes of the samples taken since the slot started.
+    pub(crate) fn lifetimes(&self, index: usize) -> Lifetimes {
+        let mut tables = self.lock();
+        let now = tables.now();
+        let slot = tables.lifetime_slots[index];
+        let start = slot.start.unwrap_or(0);
+        let mut outlived = Histogram::new();
+        for entry in tables.entries.iter().filter(|entry| entry.time >= start) {
+            outlived.record(now.saturating_sub(entry.time) as usize);
+        }
+        Lifetimes {
+            freed: slot.freed,
+            outlived,
+        }
+    }
+
+    /// Collects the samples into a profile.
+    pub(crate) fn profile(&self) -> HeapProfile {
+        let interval = self.interval.load(Ordering::SeqCst).max(1);
+        let (sites, (now, peak, peak_time)) = self.copy(
+            |tables| &tables.sites,
+            |tables| (tables.now(), tables.peak, tables.peak_time),
+        );
+        let mut samples: Vec<HeapSample> = sites
+            .iter()
+            .map(|site: &Site| HeapSample {
+                frames: site.frames.as_slice().to_vec(),
+                sampled_allocations: site.live.sampled_allocations,
+                sampled_bytes: site.live.sampled_bytes,
+                allocations: site.live.allocations,
+                bytes: site.live.bytes,
+                total_allocations: site.total.allocations,
+                total_bytes: site.total.bytes,
+                max: site.max,
+                at_peak: site.at_peak,
+                lifetimes: site.lifetimes + site.live.allocations * now as f64 - site.live_times,
+            })
+            .collect();
+        samples.sort_by(|a, b| {
+            b.bytes
+                .total_cmp(&a.bytes)
+                .then(b.total_bytes.total_cmp(&a.total_bytes))
+        });
+        HeapProfile {
+            sample_interval: interval,
+            duration: Duration::from_nanos(now),
+            peak_bytes: peak.round() as usize,
+            peak_time: Duration::from_nanos(peak_time),
+            samples,
+        }
+    }
+
+    /// Returns the size and backtrace of each live sample taken since `since`.
+    pub(crate) fn live(&self, since: Instant) -> Vec<(usize, Vec<usize>)> {
+        let (entries, start) = self.copy(|tables| &tables.entries, |tables| tables.start);
+        // Sample times count from the first sample
+        let since = start.map_or(0, |start| since.saturating_duration_since(start).as_nanos() as u64);
+        entries
+            .iter()
+            .filter(|entry| entry.time >= since)
+            .map(|entry: &Entry| (entry.size, entry.frames.as_slice().to_vec()))
+            .collect()
+    }
+
+    /// Copies the filled slots of a table, along with whatever `read` takes
+    /// from the tables under the same lock.
+    ///
+    /// Nothing may be allocated while the tables are locked, since that could
+    /// need the lock again, so room is made for the slots first, and made
+    /// again if more were filled in the meantime.
+    fn copy<S, T, R, U>(&self, table: T, read: R) -> (Vec<S>, U)
+    where
+        S: Slot,
+        T: Fn(&Tables) -> &Table<S>,
+        R: FnOnce(&mut Tables) -> U,
+    {
+        let mut slots = Vec::new();
+        loop {
+            let len = table(&self.lock()).len;
+            slots.reserve(len + MIN_CAPACITY);
+            let mut tables = self.lock();
+            let filled = table(&tables);
+            if filled.len <= slots.capacity() {
+                slots.extend(filled.iter());
+                return (slots, read(&mut tables));
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Counts `size` bytes down towards the next sample, returning `true` if
+    /// they reach it.
+    fn should_sample(&self, interval: usize, size: usize) -> bool {
+        if interval == 1 {
+            return true;
+        }
+        let size = size as isize;
+        let countdown = self.countdown.fetch_sub(size, Ordering::Relaxed);
+        if countdown <= 0 || countdown > size {
+            return false;
+        }
+        self.countdown.store(self.next_countdown(interval), Ordering::Relaxed);
+        true
+    }
+
+    /// Draws the number of bytes until the next sample from an exponential
+    /// distribution with a mean of `interval`.
+    fn next_countdown(&self, interval: usize) -> isize {
+        let mut state = self.random.load(Ordering::Relaxed);
+        let random = loop {
+            let mut next = state;
+            next ^= next << 13;
+            next ^= next >> 7;
+            next ^= next << 17;
+            match self
+                .random
+                .compare_exchange_weak(state, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break next,
+                Err(actual) => state = actual,
+            }
+        };
+        // Uniform in (0, 1]
+        let uniform = ((random >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        (-uniform.ln() * interval as f64) as isize + 1
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+fn hash(ptr: usize) -> usize {
+    let hash = (ptr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize);
+    hash ^ hash >> 29
+}
+
+/// Fowler–Noll–Vo hashing, which unlike the standard library's default
+/// hasher does not allocate or read random state.
+struct FnvHasher(u64);
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+fn filter_index(ptr: usize) -> usize {
+    hash(ptr) >> (usize::BITS - FILTER_SIZE.trailing_zeros())
+}
+
+/// Returns the number of allocations of `size` bytes which one sample stands
+/// for, being the inverse of the probability that such an allocation is
+/// sampled.
+fn scale(size: usize, interval: usize) -> f64 {
+    if interval == 1 || size == 0 {
+        return 1.0;
+    }
+    1.0 / (1.0 - (-(size as f64) / interval as f64).exp())
+}
+
+/// The lifetimes, in nanoseconds, of the tracked allocations made while a
+/// `Region` was alive.
+///
+/// Only allocations sampled by the heap profiler are tracked, so the counts
+/// are complete only with a sample interval of 1. A reallocation ends the
+/// lifetime of the old allocation and begins that of the new one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Lifetimes {
+    /// Lifetimes of the allocations which were freed within the region
+    pub freed: Histogram,
+    /// Ages of the allocations which outlived the region, or are still live
+    pub outlived: Histogram,
+}
+
+/// The sampled allocations of a `StatsAlloc`, grouped by backtrace.
+///
+/// Each backtrace at which an allocation was sampled since profiling started
+/// has a sample, giving both the allocations from it which are still live and
+/// the total allocations made from it. Counts are estimated from the samples,
+/// weighting each by the inverse of the probability that it was sampled.
+/// Formatting the profile resolves the backtraces into symbols, which may be
+/// slow.
+#[derive(Clone, Debug, Default)]
+pub struct HeapProfile {
+    sample_interval: usize,
+    duration: Duration,
+    peak_bytes: usize,
+    peak_time: Duration,
+    samples: Vec<HeapSample>,
+}
+
+impl HeapProfile {
+    /// Returns the mean number of bytes allocated between samples.
+    pub fn sample_interval(&self) -> usize {
+        self.sample_interval
+    }
+
+    /// Returns the time from the first sample to when the profile was taken.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the estimated most live bytes there have been at once.
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_bytes
+    }
+
+    /// Returns the time from the first sample to the peak of live bytes.
+    pub fn peak_time(&self) -> Duration {
+        self.peak_time
+    }
+
+    /// Returns the samples taken at each backtrace, with the most live bytes
+    /// first.
+    pub fn samples(&self) -> &[HeapSample] {
+        &self.samples
+    }
+
+    /// Returns the estimated number of live allocations.
+    pub fn allocations(&self) -> usize {
+        self.samples.iter().map(HeapSample::allocations).sum()
+    }
+
+    /// Returns the estimated number of live bytes.
+    pub fn bytes(&self) -> usize {
+        self.samples.iter().map(HeapSample::bytes).sum()
+    }
+
+    /// Returns the estimated number of allocations made while profiling.
+    pub fn total_allocations(&self) -> usize {
+        self.samples.iter().map(HeapSample::total_allocations).sum()
+    }
+
+    /// Returns the estimated number of bytes allocated while profiling.
+    pub fn total_bytes(&self) -> usize {
+        self.samples.iter().map(HeapSample::total_bytes).sum()
+    }
+}
+
+impl fmt::Display for HeapProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "heap profile: {} allocations, {} bytes live (sampled every {} bytes)",
+            self.allocations(),
+            self.bytes(),
+            self.sample_interval
+        )?;
+        for sample in self.samples.iter().filter(|sample| sample.sampled_allocations > 0) {
+            write!(
+                f,
+                "\n\n{} bytes in {} allocations ({} sampled)",
+                sample.bytes(),
+                sample.allocations(),
+                sample.sampled_allocations
+            )?;
+            for symbol in sample.resolve() {
+                write!(f, "\n    {}", symbol)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sampled allocations made from one backtrace.
+#[derive(Clone, Debug)]
+pub struct HeapSample {
+    frames: Vec<usize>,
+    sampled_allocations: usize,
+    sampled_bytes: usize,
+    allocations: f64,
+    bytes: f64,
+    total_allocations: f64,
+    total_bytes: f64,
+    max: (f64, f64),
+    at_peak: (f64, f64),
+    /// Nanoseconds
+    lifetimes: f64,
+}
+
+impl HeapSample {
+    /// Returns the instruction pointers of the backtrace, innermost first.
+    ///
+    /// The backtrace begins inside the profiler; `resolve()` leaves out the
+    /// frames of the allocator.
+    pub fn frames(&self) -> &[usize] {
+        &self.frames
+    }
+
+    /// Returns the number of live allocations which were sampled.
+    pub fn sampled_allocations(&self) -> usize {
+        self.sampled_allocations
+    }
+
+    /// Returns the number of live bytes which were sampled.
+    pub fn sampled_bytes(&self) -> usize {
+        self.sampled_bytes
+    }
+
+    /// Returns the estimated number of live allocations.
+    pub fn allocations(&self) -> usize {
+        self.allocations.round() as usize
+    }
+
+    /// Returns the estimated number of live bytes.
+    pub fn bytes(&self) -> usize {
+        self.bytes.round() as usize
+    }
+
+    /// Returns the estimated number of allocations made while profiling,
+    /// including those since freed.
+    pub fn total_allocations(&self) -> usize {
+        self.total_allocations.round() as usize
+    }
+
+    /// Returns the estimated number of bytes allocated while profiling,
+    /// including those since freed.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes.round() as usize
+    }
+
+    /// Returns the estimated number of live allocations when the backtrace
+    /// had the most live bytes.
+    pub fn max_allocations(&self) -> usize {
+        self.max.0.round() as usize
+    }
+
+    /// Returns the estimated most live bytes the backtrace has had at once.
+    pub fn max_bytes(&self) -> usize {
+        self.max.1.round() as usize
+    }
+
+    /// Returns the estimated number of live allocations when the whole heap
+    /// had the most live bytes.
+    pub fn allocations_at_peak(&self) -> usize {
+        self.at_peak.0.round() as usize
+    }
+
+    /// Returns the estimated live bytes when the whole heap had the most.
+    pub fn bytes_at_peak(&self) -> usize {
+        self.at_peak.1.round() as usize
+    }
+
+    /// Returns the estimated sum of the lifetimes of the allocations, those
+    /// still live counting until the profile was taken.
+    pub fn total_lifetime(&self) -> Duration {
+        Duration::from_nanos(self.lifetimes.max(0.0).round() as u64)
+    }
+
+    /// Resolves the backtrace into symbols, innermost first, leaving out the
+    /// frames of the allocator itself. A frame into which calls were inlined
+    /// resolves to several symbols.
+    pub fn resolve(&self) -> Vec<Symbol> {
+        resolve(&self.frames)
+    }
+}
+
+/// Resolves a backtrace captured by the profiler into symbols, leaving out
+/// the frames of the allocator.
+pub(crate) fn resolve(frames: &[usize]) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for &address in frames {
+        let start = symbols.len();
+        backtrace::resolve(address as *mut c_void, |symbol| {
+            symbols.push(Symbol {
+                address,
+                name: symbol.name().map(|name| format!("{:#}", name)),
+                file: symbol.filename().map(Path::to_path_buf),
+                line: symbol.lineno(),
+            });
+        });
+        if symbols.len() == start {
+            symbols.push(Symbol {
+                address,
+                name: None,
+                file: None,
+                line: None,
+            });
+        }
+    }
+    match symbols.iter().rposition(Symbol::is_allocator) {
+        Some(last) => symbols.split_off(last + 1),
+        None => symbols,
+    }
+}
+
+/// A resolved frame of a backtrace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Instruction pointer of the frame
+    pub address: usize,
+    /// Demangled name of the function, if known
+    pub name: Option<String>,
+    /// Source file of the function, if known
+    pub file: Option<PathBuf>,
+    /// Line in the source file, if known
+    pub line: Option<u32>,
+}
+
+impl Symbol {
+    /// Whether the frame belongs to an allocator or the machinery calling it.
+    fn is_allocator(&self) -> bool {
+        let name = match self.name {
+            Some(ref name) => name,
+            None => return false,
+        };
+        name.starts_with("__rust_")
+            || name.starts_with("__rustc::__rust_")
+            || name.contains(" as core::alloc::global::GlobalAlloc>::")
+            || name.contains(" as core::alloc::Allocator>::")
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => f.write_str(name)?,
+            None => write!(f, "{:#x}", self.address)?,
+        }
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, " at {}:{}", file.display(), line),
+            (Some(file), None) => write!(f, " at {}", file.display()),
+            _ => Ok(()),
+        }
+    }
+}
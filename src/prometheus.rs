@@ -0,0 +1,174 @@
+use std::{alloc::GlobalAlloc, fmt};
+use Stats;
+use StatsAlloc;
+
+/// Renders allocator statistics in the Prometheus text exposition format, or
+/// in OpenMetrics.
+///
+/// The encoder allocates nothing itself, and writes to any `fmt::Write`. To
+/// keep scraping from perturbing the numbers it reports, write into a buffer
+/// allocated before the statistics are read, or from outside the instrumented
+/// allocator.
+///
+/// ```
+/// # use stats_alloc::{PrometheusEncoder, StatsAlloc};
+/// # use std::alloc::System;
+/// let alloc = StatsAlloc::new(System);
+/// let mut body = String::with_capacity(4096);
+/// PrometheusEncoder::new()
+///     .prefix("myapp_heap")
+///     .labels(&[("instance", "a")])
+///     .encode(&alloc, &mut body)?;
+/// assert!(body.contains("myapp_heap_allocations_total{instance=\"a\"} 0\n"));
+/// # Ok::<(), std::fmt::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct PrometheusEncoder<'a> {
+    prefix: &'a str,
+    labels: &'a [(&'a str, &'a str)],
+    open_metrics: bool,
+}
+
+/// Whether a metric accumulates or may go up and down.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// A metric, its help text, and how to read it from `Stats`.
+type Metric = (&'static str, Kind, &'static str, fn(&Stats) -> i128);
+
+const METRICS: [Metric; 9] = [
+    (
+        "allocations",
+        Kind::Counter,
+        "Count of allocation operations",
+        |stats| stats.allocations.into(),
+    ),
+    (
+        "deallocations",
+        Kind::Counter,
+        "Count of deallocation operations",
+        |stats| stats.deallocations.into(),
+    ),
+    (
+        "reallocations",
+        Kind::Counter,
+        "Count of reallocation operations",
+        |stats| stats.reallocations.into(),
+    ),
+    (
+        "failures",
+        Kind::Counter,
+        "Count of allocation and reallocation requests which failed",
+        |stats| stats.failures.into(),
+    ),
+    (
+        "allocated_bytes",
+        Kind::Counter,
+        "Total bytes requested by allocations",
+        |stats| stats.bytes_allocated.into(),
+    ),
+    (
+        "deallocated_bytes",
+        Kind::Counter,
+        "Total bytes freed by deallocations",
+        |stats| stats.bytes_deallocated.into(),
+    ),
+    (
+        "live_allocations",
+        Kind::Gauge,
+        "Allocations not yet deallocated",
+        |stats| stats.live_allocations.into(),
+    ),
+    ("live_bytes", Kind::Gauge, "Bytes currently allocated", |stats| {
+        stats.bytes_live.into()
+    }),
+    (
+        "peak_live_bytes",
+        Kind::Gauge,
+        "Highest number of bytes allocated at once",
+        |stats| stats.bytes_peak.into(),
+    ),
+];
+
+impl<'a> PrometheusEncoder<'a> {
+    /// Creates an encoder for metrics named `stats_alloc_*`, without labels.
+    pub fn new() -> Self {
+        PrometheusEncoder {
+            prefix: "stats_alloc",
+            labels: &[],
+            open_metrics: false,
+        }
+    }
+
+    /// Sets the prefix of the metric names, which are joined to it by `_`.
+    pub fn prefix(mut self, prefix: &'a str) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Sets the labels given to every metric, as pairs of name and value.
+    /// Values are escaped as they are written.
+    pub fn labels(mut self, labels: &'a [(&'a str, &'a str)]) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Sets whether to write OpenMetrics, in which counters are described
+    /// without their `_total` suffix and the exposition ends with `# EOF`.
+    pub fn open_metrics(mut self, open_metrics: bool) -> Self {
+        self.open_metrics = open_metrics;
+        self
+    }
+
+    /// Writes the current statistics of `alloc`.
+    pub fn encode<T: GlobalAlloc, O, W: fmt::Write>(&self, alloc: &StatsAlloc<T, O>, writer: &mut W) -> fmt::Result {
+        self.encode_stats(&alloc.stats(), writer)
+    }
+
+    /// Writes `stats`, as `encode` does.
+    pub fn encode_stats<W: fmt::Write>(&self, stats: &Stats, writer: &mut W) -> fmt::Result {
+        for &(name, kind, help, value) in &METRICS {
+            let suffix = if kind == Kind::Counter { "_total" } else { "" };
+            let family = if self.open_metrics { "" } else { suffix };
+            let kind = if kind == Kind::Counter { "counter" } else { "gauge" };
+            writeln!(writer, "# HELP {}_{}{} {}", self.prefix, name, family, help)?;
+            writeln!(writer, "# TYPE {}_{}{} {}", self.prefix, name, family, kind)?;
+            write!(writer, "{}_{}{}", self.prefix, name, suffix)?;
+            self.write_labels(writer)?;
+            writeln!(writer, " {}", value(stats))?;
+        }
+        if self.open_metrics {
+            writer.write_str("# EOF\n")?;
+        }
+        Ok(())
+    }
+
+    fn write_labels<W: fmt::Write>(&self, writer: &mut W) -> fmt::Result {
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+        for (i, &(name, value)) in self.labels.iter().enumerate() {
+            writer.write_str(if i == 0 { "{" } else { "," })?;
+            write!(writer, "{}=\"", name)?;
+            for c in value.chars() {
+                match c {
+                    '\\' => writer.write_str("\\\\")?,
+                    '"' => writer.write_str("\\\"")?,
+                    '\n' => writer.write_str("\\n")?,
+                    c => writer.write_char(c)?,
+                }
+            }
+            writer.write_char('"')?;
+        }
+        writer.write_char('}')
+    }
+}
+
+impl<'a> Default for PrometheusEncoder<'a> {
+    fn default() -> Self {
+        PrometheusEncoder::new()
+    }
+}
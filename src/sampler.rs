@@ -0,0 +1,389 @@
+use std::{
+    alloc::{handle_alloc_error, GlobalAlloc, Layout},
+    fmt,
+    io::{self, BufWriter, Write},
+    ptr,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+use Stats;
+use StatsAlloc;
+
+/// Statistics taken by a `Sampler`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Time since the sampler started
+    pub elapsed: Duration,
+    /// The statistics of the allocator at that time
+    pub stats: Stats,
+}
+
+/// Rates per second at which the counters of an allocator grew between two
+/// samples.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Rates {
+    /// Allocation operations per second
+    pub allocations: f64,
+    /// Deallocation operations per second
+    pub deallocations: f64,
+    /// Reallocation operations per second
+    pub reallocations: f64,
+    /// Bytes requested by allocations per second
+    pub bytes_allocated: f64,
+    /// Bytes freed by deallocations per second
+    pub bytes_deallocated: f64,
+}
+
+impl Rates {
+    /// Returns the rates between an earlier and a later sample.
+    ///
+    /// Counters which went down between the samples, as they do when the
+    /// allocator is reset, count as unchanged. If no time passed between the
+    /// samples every rate is zero.
+    pub fn between(earlier: &Sample, later: &Sample) -> Self {
+        let seconds = later.elapsed.saturating_sub(earlier.elapsed).as_secs_f64();
+        if seconds == 0.0 {
+            return Rates::default();
+        }
+        let change = later.stats.saturating_sub(earlier.stats);
+        Rates {
+            allocations: change.allocations as f64 / seconds,
+            deallocations: change.deallocations as f64 / seconds,
+            reallocations: change.reallocations as f64 / seconds,
+            bytes_allocated: change.bytes_allocated as f64 / seconds,
+            bytes_deallocated: change.bytes_deallocated as f64 / seconds,
+        }
+    }
+}
+
+/// Samples taken by a `Sampler`, oldest first.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Series {
+    samples: Vec<Sample>,
+}
+
+impl Series {
+    /// Returns the samples, oldest first.
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Returns the rates between each sample and the one before it, so there
+    /// is one fewer than there are samples.
+    pub fn rates(&self) -> Vec<Rates> {
+        self.samples
+            .windows(2)
+            .map(|pair| Rates::between(&pair[0], &pair[1]))
+            .collect()
+    }
+
+    /// Returns the rates between the oldest and the newest samples.
+    pub fn mean_rates(&self) -> Rates {
+        match (self.samples.first(), self.samples.last()) {
+            (Some(first), Some(last)) => Rates::between(first, last),
+            _ => Rates::default(),
+        }
+    }
+
+    /// Writes the samples as CSV, with a header and one row per sample.
+    ///
+    /// Each row holds the time in seconds since the sampler started, the
+    /// counters of the sample, and the rates since the previous sample, which
+    /// are zero in the first row.
+    pub fn write_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        let header = row(
+            None,
+            &Sample {
+                elapsed: Duration::ZERO,
+                stats: Stats::default(),
+            },
+        );
+        for (i, (name, _)) in header.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(name.as_bytes())?;
+        }
+        writer.write_all(b"\n")?;
+        for fields in self.rows() {
+            for (i, (_, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                write!(writer, "{}", value)?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the samples as a JSON array with an object per sample, whose
+    /// fields are the columns written by `write_csv()`.
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(b"[")?;
+        for (i, fields) in self.rows().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(b"\n{")?;
+            for (j, (name, value)) in fields.iter().enumerate() {
+                if j > 0 {
+                    writer.write_all(b",")?;
+                }
+                write!(writer, "\"{}\":{}", name, value)?;
+            }
+            writer.write_all(b"}")?;
+        }
+        writer.write_all(b"\n]\n")?;
+        writer.flush()
+    }
+
+    fn rows(&self) -> impl Iterator<Item = [(&'static str, Value); COLUMNS]> + '_ {
+        self.samples
+            .iter()
+            .enumerate()
+            .map(move |(i, sample)| row(i.checked_sub(1).map(|i| &self.samples[i]), sample))
+    }
+}
+
+/// Number of columns written for each sample.
+const COLUMNS: usize = 16;
+
+/// A number written for a sample.
+#[derive(Clone, Copy)]
+enum Value {
+    Count(u64),
+    Net(i64),
+    Real(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Count(value) => value.fmt(f),
+            Value::Net(value) => value.fmt(f),
+            Value::Real(value) => value.fmt(f),
+        }
+    }
+}
+
+/// Returns the named columns written for `sample`, which follows `previous`.
+fn row(previous: Option<&Sample>, sample: &Sample) -> [(&'static str, Value); COLUMNS] {
+    let stats = &sample.stats;
+    let rates = previous.map_or_else(Rates::default, |previous| Rates::between(previous, sample));
+    [
+        ("elapsed_seconds", Value::Real(sample.elapsed.as_secs_f64())),
+        ("allocations", Value::Count(stats.allocations)),
+        ("deallocations", Value::Count(stats.deallocations)),
+        ("reallocations", Value::Count(stats.reallocations)),
+        ("failures", Value::Count(stats.failures)),
+        ("bytes_allocated", Value::Count(stats.bytes_allocated)),
+        ("bytes_deallocated", Value::Count(stats.bytes_deallocated)),
+        ("bytes_reallocated", Value::Net(stats.bytes_reallocated)),
+        ("live_allocations", Value::Net(stats.live_allocations)),
+        ("bytes_live", Value::Net(stats.bytes_live)),
+        ("bytes_peak", Value::Count(stats.bytes_peak)),
+        ("allocations_per_second", Value::Real(rates.allocations)),
+        ("deallocations_per_second", Value::Real(rates.deallocations)),
+        ("reallocations_per_second", Value::Real(rates.reallocations)),
+        ("bytes_allocated_per_second", Value::Real(rates.bytes_allocated)),
+        ("bytes_deallocated_per_second", Value::Real(rates.bytes_deallocated)),
+    ]
+}
+
+/// Samples kept in memory allocated from the inner allocator, overwriting
+/// the oldest once full.
+#[derive(Debug)]
+struct Ring {
+    samples: *mut Sample,
+    capacity: usize,
+    len: usize,
+    next: usize,
+}
+
+// The ring owns its samples, and is only reached through a `Mutex`.
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn push(&mut self, sample: Sample) {
+        unsafe { ptr::write(self.samples.add(self.next), sample) };
+        self.next = (self.next + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+    }
+
+    fn latest(&self, back: usize) -> Option<Sample> {
+        if back >= self.len {
+            return None;
+        }
+        let index = (self.next + self.capacity - 1 - back) % self.capacity;
+        Some(unsafe { *self.samples.add(index) })
+    }
+
+    fn to_vec(&self) -> Vec<Sample> {
+        (0..self.len).rev().filter_map(|back| self.latest(back)).collect()
+    }
+}
+
+/// State shared between a `Sampler` and its thread.
+#[derive(Debug)]
+struct Shared<T: GlobalAlloc + 'static, O: 'static> {
+    alloc: &'static StatsAlloc<T, O>,
+    layout: Layout,
+    ring: Mutex<Ring>,
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl<T: GlobalAlloc + 'static, O: 'static> Shared<T, O> {
+    fn run(&self, interval: Duration) {
+        let start = Instant::now();
+        let mut next = start;
+        let mut stopped = lock(&self.stopped);
+        loop {
+            let sample = Sample {
+                elapsed: start.elapsed(),
+                stats: self.alloc.stats(),
+            };
+            lock(&self.ring).push(sample);
+            if *stopped {
+                return;
+            }
+            next += interval;
+            loop {
+                let now = Instant::now();
+                if *stopped || now >= next {
+                    break;
+                }
+                stopped = self
+                    .wake
+                    .wait_timeout(stopped, next - now)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        }
+    }
+}
+
+impl<T: GlobalAlloc + 'static, O: 'static> Drop for Shared<T, O> {
+    fn drop(&mut self) {
+        let ring = lock(&self.ring);
+        unsafe { self.alloc.inner.dealloc(ring.samples as *mut u8, self.layout) };
+    }
+}
+
+fn lock<S>(mutex: &Mutex<S>) -> MutexGuard<'_, S> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Periodically samples the statistics of an allocator on a background
+/// thread, keeping the latest samples to report rates over time.
+///
+/// The samples are kept in a ring buffer allocated from the allocator
+/// wrapped by the `StatsAlloc`, so that it does not count itself, and taking
+/// a sample allocates nothing. Starting the thread and reading the samples
+/// out as a `Series` do allocate through the `StatsAlloc`.
+///
+/// The sampler takes a last sample and stops when it is stopped or dropped.
+///
+/// ```
+/// # use stats_alloc::{Sampler, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// # use std::time::Duration;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// let sampler = Sampler::start(GLOBAL, Duration::from_millis(100), 600)?;
+/// // ...
+/// let series = sampler.stop();
+/// println!("{:.0} allocations/s", series.mean_rates().allocations);
+/// series.write_csv(std::io::stdout())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Sampler<T: GlobalAlloc + 'static, O: 'static = ()> {
+    shared: Arc<Shared<T, O>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: GlobalAlloc + Sync + 'static, O: Sync + 'static> Sampler<T, O> {
+    /// Starts sampling `alloc` every `interval`, keeping the latest
+    /// `capacity` samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` or `capacity` is zero.
+    pub fn start(alloc: &'static StatsAlloc<T, O>, interval: Duration, capacity: usize) -> io::Result<Self> {
+        assert!(interval > Duration::ZERO, "sample interval must not be zero");
+        assert!(capacity > 0, "sampler must keep at least one sample");
+        let layout = Layout::array::<Sample>(capacity).expect("sampler capacity is too large");
+        let samples = unsafe { alloc.inner.alloc(layout) } as *mut Sample;
+        if samples.is_null() {
+            handle_alloc_error(layout);
+        }
+        let shared = Arc::new(Shared {
+            alloc,
+            layout,
+            ring: Mutex::new(Ring {
+                samples,
+                capacity,
+                len: 0,
+                next: 0,
+            }),
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            Builder::new()
+                .name("stats_alloc sampler".to_owned())
+                .spawn(move || shared.run(interval))?
+        };
+        Ok(Sampler {
+            shared,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl<T: GlobalAlloc + 'static, O: 'static> Sampler<T, O> {
+    /// Returns the latest sample, if one has been taken.
+    pub fn latest(&self) -> Option<Sample> {
+        lock(&self.shared.ring).latest(0)
+    }
+
+    /// Returns the rates between the two latest samples, once two have been
+    /// taken.
+    pub fn rates(&self) -> Option<Rates> {
+        let ring = lock(&self.shared.ring);
+        Some(Rates::between(&ring.latest(1)?, &ring.latest(0)?))
+    }
+
+    /// Returns the samples kept so far.
+    pub fn series(&self) -> Series {
+        let samples = lock(&self.shared.ring).to_vec();
+        Series { samples }
+    }
+
+    /// Takes a last sample and stops the sampler, returning the samples kept.
+    pub fn stop(mut self) -> Series {
+        self.halt();
+        self.series()
+    }
+
+    fn halt(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            *lock(&self.shared.stopped) = true;
+            self.shared.wake.notify_all();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T: GlobalAlloc + 'static, O: 'static> Drop for Sampler<T, O> {
+    fn drop(&mut self) {
+        self.halt();
+    }
+}
@@ -0,0 +1,143 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{alloc::GlobalAlloc, ops, time::SystemTime};
+#[cfg(feature = "serde")]
+use std::{
+    convert::TryFrom,
+    fmt,
+    time::{Duration, UNIX_EPOCH},
+};
+use Stats;
+use StatsAlloc;
+
+/// Statistics labelled and stamped with the time they were taken, so that
+/// they can be stored and compared later.
+///
+/// Subtracting one snapshot from another gives the change between them, as
+/// `Region::change()` does, with counts stopping at zero if the earlier
+/// snapshot is the greater.
+///
+/// With the `serde` feature, a snapshot serializes as a map with a
+/// `version` of 1, its `label`, its `timestamp` in nanoseconds since the Unix
+/// epoch, and its `stats`. Only the versions this crate knows of can be
+/// deserialized.
+///
+/// ```
+/// # use stats_alloc::{Snapshot, StatsAlloc};
+/// # use std::alloc::System;
+/// let alloc = StatsAlloc::new(System);
+/// let before = Snapshot::new("before", &alloc);
+/// let v: Vec<u8> = Vec::with_capacity(64);
+/// let after = Snapshot::new("after", &alloc);
+/// let change = after - before;
+/// # drop(v);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "SnapshotRepr", try_from = "SnapshotRepr")
+)]
+pub struct Snapshot {
+    /// What the statistics describe
+    pub label: String,
+    /// When the statistics were taken
+    pub timestamp: SystemTime,
+    /// The statistics
+    pub stats: Stats,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the statistics of `alloc`.
+    pub fn new<L: Into<String>, T: GlobalAlloc, O>(label: L, alloc: &StatsAlloc<T, O>) -> Self {
+        Snapshot::from_stats(label, alloc.stats())
+    }
+
+    /// Labels `stats` and stamps them with the current time.
+    pub fn from_stats<L: Into<String>>(label: L, stats: Stats) -> Self {
+        Snapshot {
+            label: label.into(),
+            timestamp: SystemTime::now(),
+            stats,
+        }
+    }
+}
+
+impl ops::Sub for Snapshot {
+    type Output = Stats;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        &self - &rhs
+    }
+}
+
+impl ops::Sub for &Snapshot {
+    type Output = Stats;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.stats.saturating_sub(rhs.stats)
+    }
+}
+
+/// The serialized layout of a `Snapshot`.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SnapshotRepr {
+    version: u32,
+    label: String,
+    timestamp: i64,
+    stats: Stats,
+}
+
+#[cfg(feature = "serde")]
+const VERSION: u32 = 1;
+
+#[cfg(feature = "serde")]
+impl From<Snapshot> for SnapshotRepr {
+    fn from(snapshot: Snapshot) -> Self {
+        let timestamp = match snapshot.timestamp.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_nanos() as i64,
+            Err(before) => -(before.duration().as_nanos() as i64),
+        };
+        SnapshotRepr {
+            version: VERSION,
+            label: snapshot.label,
+            timestamp,
+            stats: snapshot.stats,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SnapshotRepr> for Snapshot {
+    type Error = UnknownVersion;
+
+    fn try_from(repr: SnapshotRepr) -> Result<Self, Self::Error> {
+        if repr.version != VERSION {
+            return Err(UnknownVersion(repr.version));
+        }
+        let offset = Duration::from_nanos(repr.timestamp.unsigned_abs());
+        let timestamp = if repr.timestamp >= 0 {
+            UNIX_EPOCH + offset
+        } else {
+            UNIX_EPOCH - offset
+        };
+        Ok(Snapshot {
+            label: repr.label,
+            timestamp,
+            stats: repr.stats,
+        })
+    }
+}
+
+/// The error from deserializing a snapshot of an unknown version.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+struct UnknownVersion(u32);
+
+#[cfg(feature = "serde")]
+impl fmt::Display for UnknownVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown snapshot version {}, expected {}", self.0, VERSION)
+    }
+}
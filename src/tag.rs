@@ -0,0 +1,360 @@
+use counters::Counters;
+#[cfg(feature = "nightly")]
+use std::{
+    alloc::{AllocError, Allocator},
+    ptr::NonNull,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    marker::PhantomData,
+    ptr,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use thread;
+use Stats;
+use StatsAlloc;
+
+/// Number of distinct tags, including `Tag::UNTAGGED`.
+pub const TAGS: usize = 16;
+
+/// Names of the tags created so far, by index.
+static NAMES: Mutex<[Option<&'static str>; TAGS]> = Mutex::new({
+    let mut names = [None; TAGS];
+    names[0] = Some("untagged");
+    names
+});
+
+fn names() -> MutexGuard<'static, [Option<&'static str>; TAGS]> {
+    NAMES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A label for the allocations made by one part of a program, such as a
+/// subsystem of a service.
+///
+/// Each thread has a current tag, which is `Tag::UNTAGGED` until another is
+/// entered. Every `StatsAlloc` keeps statistics per tag alongside its totals.
+/// An allocation is attributed to the tag current when it was made, and so
+/// are its reallocation and deallocation, whichever tag is current at the
+/// time. Failed requests are counted against the current tag.
+///
+/// To remember the tag of each allocation, it is stored in one byte just
+/// past the memory handed out. With the `tags` feature every request made to
+/// the wrapped allocator therefore grows by one byte, which the statistics
+/// leave out: the wrapped allocator holds `live_allocations` bytes more than
+/// `bytes_live`, plus whatever it rounds the extra byte up to.
+///
+/// Each tag also has counters of its own, so the `tags` feature makes every
+/// `StatsAlloc` larger by about 4 KiB, or by about 36 KiB with the
+/// `sharded-counters` feature, whose shards every tag has too.
+///
+/// ```
+/// # use stats_alloc::{StatsAlloc, Tag, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// let parser = Tag::new("parser");
+/// let tokens = {
+///     let _tag = parser.enter();
+///     Vec::<u32>::with_capacity(16)
+/// };
+/// assert!(GLOBAL.tag_stats(parser).bytes_live >= 64);
+/// drop(tokens);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tag(u8);
+
+impl Tag {
+    /// The tag of allocations made while no other tag is entered
+    pub const UNTAGGED: Tag = Tag(0);
+
+    /// Returns the tag with the given name, creating it if there is none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `TAGS` tags, including `Tag::UNTAGGED`, already exist.
+    pub fn new(name: &'static str) -> Self {
+        let mut names = names();
+        for (index, slot) in names.iter_mut().enumerate() {
+            match *slot {
+                Some(existing) if existing == name => return Tag(index as u8),
+                Some(_) => {},
+                None => {
+                    *slot = Some(name);
+                    return Tag(index as u8);
+                },
+            }
+        }
+        drop(names);
+        panic!("no more than {} tags may be created", TAGS);
+    }
+
+    /// Returns every tag created so far, starting with `Tag::UNTAGGED`.
+    pub fn all() -> Vec<Tag> {
+        let count = names().iter().take_while(|name| name.is_some()).count();
+        (0..count).map(|index| Tag(index as u8)).collect()
+    }
+
+    /// Returns the current tag of the calling thread.
+    pub fn current() -> Self {
+        Tag(thread::tag())
+    }
+
+    /// Returns the name of the tag.
+    pub fn name(self) -> &'static str {
+        names()[self.index()].unwrap_or("")
+    }
+
+    /// Returns the position of the tag, less than `TAGS`.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Makes this the current tag of the calling thread until the returned
+    /// guard is dropped, when the previous tag is restored.
+    pub fn enter(self) -> TagGuard {
+        TagGuard {
+            previous: thread::set_tag(self.0),
+            not_send: PhantomData,
+        }
+    }
+}
+
+/// Keeps a `Tag` current on the calling thread, restoring the previous tag
+/// when dropped.
+#[derive(Debug)]
+#[must_use = "the tag is left as soon as the guard is dropped"]
+pub struct TagGuard {
+    previous: u8,
+    not_send: PhantomData<*const ()>,
+}
+
+impl Drop for TagGuard {
+    fn drop(&mut self) {
+        thread::set_tag(self.previous);
+    }
+}
+
+/// A snapshot of the statistics of one tag of an allocator, which can be
+/// used to determine the allocation changes attributed to the tag while the
+/// `TagRegion` is alive.
+///
+/// `bytes_peak` is the highest `bytes_live` the tag has reached.
+#[derive(Debug)]
+pub struct TagRegion<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    alloc: &'a StatsAlloc<T, O>,
+    tag: Tag,
+    initial_stats: Stats,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> TagRegion<'a, T, O> {
+    /// Creates a new region using the statistics of `tag` from the given
+    /// instrumented allocator.
+    #[inline]
+    pub fn new(alloc: &'a StatsAlloc<T, O>, tag: Tag) -> Self {
+        TagRegion {
+            alloc,
+            tag,
+            initial_stats: alloc.tag_stats(tag),
+        }
+    }
+
+    /// Returns the tag whose statistics are measured.
+    #[inline]
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+
+    /// Returns the statistics as of instantiation or the last reset.
+    #[inline]
+    pub fn initial(&self) -> Stats {
+        self.initial_stats
+    }
+
+    /// Returns the difference between the currently reported statistics of
+    /// the tag and those provided by `initial()`.
+    #[inline]
+    pub fn change(&self) -> Stats {
+        self.alloc.tag_stats(self.tag).saturating_sub(self.initial_stats)
+    }
+
+    /// Returns the difference between the currently reported statistics of
+    /// the tag and those provided by `initial()`, resetting initial to the
+    /// latest reported statistics.
+    #[inline]
+    pub fn change_and_reset(&mut self) -> Stats {
+        let latest = self.alloc.tag_stats(self.tag);
+        let diff = latest.saturating_sub(self.initial_stats);
+        self.initial_stats = latest;
+        diff
+    }
+
+    /// Resets the initial statistics to the latest reported statistics of
+    /// the tag.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.initial_stats = self.alloc.tag_stats(self.tag);
+    }
+}
+
+/// The counters of each tag of a `StatsAlloc`, which have no peak slots, as
+/// a `TagRegion` reports no peak of its own.
+#[derive(Debug)]
+pub(crate) struct Tags {
+    counters: [Counters<0>; TAGS],
+}
+
+impl Tags {
+    pub(crate) const fn new() -> Self {
+        Tags {
+            counters: [const { Counters::new() }; TAGS],
+        }
+    }
+
+    pub(crate) fn load(&self, tag: Tag) -> Stats {
+        self.counters[tag.index()].load()
+    }
+
+    pub(crate) fn take(&self) {
+        for counters in &self.counters {
+            counters.take();
+        }
+    }
+
+    pub(crate) fn record_alloc(&self, tag: u8, size: usize) {
+        let counters = &self.counters[tag as usize];
+        counters.add_live(size);
+        counters.record_alloc(size);
+    }
+
+    pub(crate) fn record_dealloc(&self, tag: u8, size: usize) {
+        self.counters[tag as usize].record_dealloc(size);
+    }
+
+    pub(crate) fn record_realloc(&self, tag: u8, old_size: usize, new_size: usize) {
+        let counters = &self.counters[tag as usize];
+        counters.add_live(new_size.saturating_sub(old_size));
+        counters.record_realloc(old_size, new_size);
+    }
+
+    #[cfg(feature = "nightly")]
+    pub(crate) fn record_grow(&self, tag: u8) {
+        self.counters[tag as usize].record_grow();
+    }
+
+    #[cfg(feature = "nightly")]
+    pub(crate) fn record_shrink(&self, tag: u8) {
+        self.counters[tag as usize].record_shrink();
+    }
+
+    /// Records a failed request against the current tag.
+    pub(crate) fn record_failure(&self) {
+        self.counters[thread::tag() as usize].record_failure();
+    }
+}
+
+impl Default for Tags {
+    fn default() -> Self {
+        Tags::new()
+    }
+}
+
+/// Returns the layout of a block holding an allocation of `layout` followed
+/// by its tag.
+fn block_layout(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_add(1)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// Returns the tag of an allocation of `size` bytes made through `alloc` or
+/// `allocate`.
+#[inline]
+pub(crate) unsafe fn tag_of(ptr: *mut u8, size: usize) -> u8 {
+    *ptr.add(size)
+}
+
+/// Allocates a block from `inner`, tagged with the current tag.
+pub(crate) unsafe fn alloc<A: GlobalAlloc>(inner: &A, layout: Layout, zeroed: bool) -> *mut u8 {
+    let block_layout = match block_layout(layout) {
+        Some(block_layout) => block_layout,
+        None => return ptr::null_mut(),
+    };
+    let ptr = if zeroed {
+        inner.alloc_zeroed(block_layout)
+    } else {
+        inner.alloc(block_layout)
+    };
+    if !ptr.is_null() {
+        *ptr.add(layout.size()) = thread::tag();
+    }
+    ptr
+}
+
+pub(crate) unsafe fn dealloc<A: GlobalAlloc>(inner: &A, ptr: *mut u8, layout: Layout) {
+    let block_layout = block_layout(layout).expect("layout was valid when allocated");
+    inner.dealloc(ptr, block_layout)
+}
+
+/// Reallocates the block of an allocation, moving its tag to the new end.
+pub(crate) unsafe fn realloc<A: GlobalAlloc>(inner: &A, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let new_block_layout = match block_layout(Layout::from_size_align_unchecked(new_size, layout.align())) {
+        Some(new_block_layout) => new_block_layout,
+        None => return ptr::null_mut(),
+    };
+    let tag = tag_of(ptr, layout.size());
+    let block_layout = block_layout(layout).expect("layout was valid when allocated");
+    let new_ptr = inner.realloc(ptr, block_layout, new_block_layout.size());
+    if !new_ptr.is_null() {
+        *new_ptr.add(new_size) = tag;
+    }
+    new_ptr
+}
+
+/// Allocates a block with `allocate`, tagged with the current tag.
+#[cfg(feature = "nightly")]
+pub(crate) fn allocate<F>(layout: Layout, allocate: F) -> Result<NonNull<[u8]>, AllocError>
+where F: FnOnce(Layout) -> Result<NonNull<[u8]>, AllocError> {
+    let block = allocate(block_layout(layout).ok_or(AllocError)?)?;
+    Ok(unsafe { tagged(block, layout.size(), thread::tag()) })
+}
+
+#[cfg(feature = "nightly")]
+pub(crate) unsafe fn deallocate<A: Allocator>(inner: &A, ptr: NonNull<u8>, layout: Layout) {
+    let block_layout = block_layout(layout).expect("layout was valid when allocated");
+    inner.deallocate(ptr, block_layout)
+}
+
+/// Grows or shrinks the block of an allocation with `resize`, moving its tag
+/// to the new end. When growing, the old tag is overwritten with zero if
+/// `zeroed` is set, as the memory past the old end must be.
+#[cfg(feature = "nightly")]
+pub(crate) unsafe fn resize<F>(
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+    zeroed: bool,
+    resize: F,
+) -> Result<NonNull<[u8]>, AllocError>
+where
+    F: FnOnce(NonNull<u8>, Layout, Layout) -> Result<NonNull<[u8]>, AllocError>,
+{
+    let tag = tag_of(ptr.as_ptr(), old_layout.size());
+    let new_block_layout = block_layout(new_layout).ok_or(AllocError)?;
+    let block_layout = block_layout(old_layout).expect("layout was valid when allocated");
+    let new_block = resize(ptr, block_layout, new_block_layout)?;
+    if zeroed && new_layout.size() > old_layout.size() {
+        *new_block.cast::<u8>().as_ptr().add(old_layout.size()) = 0;
+    }
+    Ok(tagged(new_block, new_layout.size(), tag))
+}
+
+/// Writes `tag` after the first `size` bytes of `block`, returning those.
+///
+/// The rest of the block is left out, so that callers cannot overwrite the
+/// tag, and must deallocate with the size they asked for.
+#[cfg(feature = "nightly")]
+unsafe fn tagged(block: NonNull<[u8]>, size: usize, tag: u8) -> NonNull<[u8]> {
+    let ptr = block.cast::<u8>();
+    *ptr.as_ptr().add(size) = tag;
+    NonNull::slice_from_raw_parts(ptr, size)
+}
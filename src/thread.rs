@@ -0,0 +1,471 @@
+use counters::Counters;
+#[cfg(feature = "sharded-counters")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    alloc::GlobalAlloc,
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+};
+use Stats;
+use StatsAlloc;
+
+/// Number of allocators whose statistics each thread can keep apart at once.
+const WATCH_SLOTS: usize = 8;
+
+/// Statistics of a thread which has made no requests.
+const ZERO: Stats = Stats {
+    allocations: 0,
+    deallocations: 0,
+    reallocations: 0,
+    grows: 0,
+    shrinks: 0,
+    bytes_allocated: 0,
+    bytes_deallocated: 0,
+    bytes_reallocated: 0,
+    failures: 0,
+    live_allocations: 0,
+    bytes_live: 0,
+    bytes_peak: 0,
+};
+
+/// Statistics of threads which have exited.
+static EXITED: Counters = Counters::new();
+
+/// Counter shard to assign to the next thread which asks for one.
+#[cfg(feature = "sharded-counters")]
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The exit guard has not been registered yet.
+    Unregistered,
+    /// The exit guard is being registered, which may itself allocate.
+    Registering,
+    Registered,
+    /// The thread's statistics have been folded into `EXITED`.
+    Exited,
+}
+
+struct ThreadCounters {
+    state: Cell<State>,
+    /// Whether the thread is running an `AllocObserver` callback.
+    observing: Cell<bool>,
+    /// Whether the thread is sampling an allocation for the heap profiler.
+    #[cfg(feature = "heap-profiler")]
+    sampling: Cell<bool>,
+    #[cfg(feature = "sharded-counters")]
+    shard: Cell<usize>,
+    /// Index of the `Tag` the thread's allocations are attributed to.
+    #[cfg(feature = "tags")]
+    tag: Cell<u8>,
+    stats: UnsafeCell<Stats>,
+    /// Number of `watches` in use.
+    watching: Cell<usize>,
+    watches: [Watch; WATCH_SLOTS],
+}
+
+/// The calling thread's statistics for a single allocator, kept while a
+/// `ThreadRegion` of that allocator is alive.
+struct Watch {
+    /// Key of the allocator, or zero if the slot is free.
+    alloc: Cell<usize>,
+    /// Number of regions using the slot.
+    users: Cell<usize>,
+    stats: UnsafeCell<Stats>,
+}
+
+impl Watch {
+    const fn new() -> Self {
+        Watch {
+            alloc: Cell::new(0),
+            users: Cell::new(0),
+            stats: UnsafeCell::new(ZERO),
+        }
+    }
+}
+
+/// Folds the thread's statistics into `EXITED` when the thread exits.
+///
+/// This is kept apart from `ThreadCounters` so that the counters themselves
+/// never need a destructor, and can be used while the guard is registered.
+struct ExitGuard;
+
+thread_local! {
+    static COUNTERS: ThreadCounters = const {
+        ThreadCounters {
+            state: Cell::new(State::Unregistered),
+            observing: Cell::new(false),
+            #[cfg(feature = "heap-profiler")]
+            sampling: Cell::new(false),
+            #[cfg(feature = "sharded-counters")]
+            shard: Cell::new(usize::MAX),
+            #[cfg(feature = "tags")]
+            tag: Cell::new(0),
+            stats: UnsafeCell::new(ZERO),
+            watching: Cell::new(0),
+            watches: [const { Watch::new() }; WATCH_SLOTS],
+        }
+    };
+    static EXIT_GUARD: ExitGuard = const { ExitGuard };
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        let _ = COUNTERS.try_with(|counters| {
+            counters.state.set(State::Exited);
+            // Safety: see `update`
+            let stats = unsafe { &mut *counters.stats.get() };
+            EXITED.add(stats);
+            *stats = Stats::default();
+        });
+    }
+}
+
+/// Applies an update to the calling thread's statistics, and to those it
+/// keeps for `alloc` if any, or to those of the exited threads if this thread
+/// has already torn down its counters.
+#[inline]
+fn update<L, E>(alloc: usize, local: L, exited: E)
+where
+    L: Fn(&mut Stats),
+    E: FnOnce(&Counters),
+{
+    let _ = COUNTERS.try_with(|counters| {
+        let state = counters.state.get();
+        if state == State::Exited {
+            return exited(&EXITED);
+        }
+        if state == State::Unregistered {
+            counters.state.set(State::Registering);
+            let _ = EXIT_GUARD.try_with(|_| ());
+            counters.state.set(State::Registered);
+        }
+        // Safety: the counters are only reachable from their own thread, and
+        // no reference escapes this function. Updates never allocate, so
+        // this cannot be re-entered while the reference is held.
+        local(unsafe { &mut *counters.stats.get() });
+        if counters.watching.get() != 0 {
+            for watch in &counters.watches {
+                if watch.alloc.get() == alloc {
+                    local(unsafe { &mut *watch.stats.get() });
+                }
+            }
+        }
+    });
+}
+
+pub(crate) fn record_alloc(alloc: usize, size: usize) {
+    update(
+        alloc,
+        |stats| {
+            stats.allocations += 1;
+            stats.bytes_allocated += size as u64;
+            live_grew(stats, 1, size);
+        },
+        |exited| {
+            exited.add_live(size);
+            exited.record_alloc(size);
+        },
+    )
+}
+
+pub(crate) fn record_dealloc(alloc: usize, size: usize) {
+    update(
+        alloc,
+        |stats| {
+            stats.deallocations += 1;
+            stats.bytes_deallocated += size as u64;
+            stats.live_allocations -= 1;
+            stats.bytes_live -= size as i64;
+        },
+        |exited| exited.record_dealloc(size),
+    )
+}
+
+pub(crate) fn record_realloc(alloc: usize, old_size: usize, new_size: usize) {
+    update(
+        alloc,
+        |stats| {
+            stats.reallocations += 1;
+            if new_size > old_size {
+                let difference = new_size - old_size;
+                stats.bytes_allocated += difference as u64;
+                live_grew(stats, 0, difference);
+            } else if new_size < old_size {
+                let difference = old_size - new_size;
+                stats.bytes_deallocated += difference as u64;
+                stats.bytes_live -= difference as i64;
+            }
+            stats.bytes_reallocated += new_size as i64 - old_size as i64;
+        },
+        |exited| {
+            exited.add_live(new_size.saturating_sub(old_size));
+            exited.record_realloc(old_size, new_size);
+        },
+    )
+}
+
+#[cfg(feature = "nightly")]
+pub(crate) fn record_grow(alloc: usize) {
+    update(alloc, |stats| stats.grows += 1, |exited| exited.record_grow())
+}
+
+#[cfg(feature = "nightly")]
+pub(crate) fn record_shrink(alloc: usize) {
+    update(alloc, |stats| stats.shrinks += 1, |exited| exited.record_shrink())
+}
+
+pub(crate) fn record_failure(alloc: usize) {
+    update(alloc, |stats| stats.failures += 1, |exited| exited.record_failure())
+}
+
+fn live_grew(stats: &mut Stats, allocations: i64, bytes: usize) {
+    stats.live_allocations += allocations;
+    stats.bytes_live += bytes as i64;
+    if stats.bytes_live > 0 {
+        stats.bytes_peak = stats.bytes_peak.max(stats.bytes_live as u64);
+    }
+}
+
+/// Returns the counter shard assigned to the calling thread.
+#[cfg(feature = "sharded-counters")]
+pub(crate) fn shard_index() -> usize {
+    COUNTERS
+        .try_with(|counters| match counters.shard.get() {
+            usize::MAX => {
+                let shard = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+                counters.shard.set(shard);
+                shard
+            },
+            shard => shard,
+        })
+        .unwrap_or(0)
+}
+
+/// Runs an `AllocObserver` callback, unless the calling thread is already
+/// running one. Allocations made by an observer are counted, but not observed.
+#[inline]
+pub(crate) fn observe<F: FnOnce()>(callback: F) {
+    let _ = COUNTERS.try_with(|counters| {
+        if !counters.observing.replace(true) {
+            callback();
+            counters.observing.set(false);
+        }
+    });
+}
+
+/// Runs `callback` to sample an allocation, unless the calling thread is
+/// already sampling one. Allocations made while sampling are not sampled.
+#[cfg(feature = "heap-profiler")]
+#[inline]
+pub(crate) fn sample<F: FnOnce()>(callback: F) {
+    let _ = COUNTERS.try_with(|counters| {
+        if !counters.sampling.replace(true) {
+            callback();
+            counters.sampling.set(false);
+        }
+    });
+}
+
+/// Returns the index of the calling thread's current tag.
+#[cfg(feature = "tags")]
+#[inline]
+pub(crate) fn tag() -> u8 {
+    COUNTERS.try_with(|counters| counters.tag.get()).unwrap_or(0)
+}
+
+/// Sets the calling thread's current tag, returning the previous one.
+#[cfg(feature = "tags")]
+pub(crate) fn set_tag(tag: u8) -> u8 {
+    COUNTERS.try_with(|counters| counters.tag.replace(tag)).unwrap_or(0)
+}
+
+/// Starts keeping the calling thread's statistics for the allocator with the
+/// key `alloc` apart, returning the slot they are kept in, or `None` if every
+/// slot is in use by other allocators.
+///
+/// A new slot starts from zero, and one already kept for `alloc` is shared.
+fn watch(alloc: usize) -> Option<usize> {
+    COUNTERS
+        .try_with(|counters| {
+            let watches = &counters.watches;
+            if let Some(slot) = watches.iter().position(|watch| watch.alloc.get() == alloc) {
+                let watch = &watches[slot];
+                watch.users.set(watch.users.get() + 1);
+                return Some(slot);
+            }
+            let slot = watches.iter().position(|watch| watch.alloc.get() == 0)?;
+            let watch = &watches[slot];
+            // Safety: see `update`
+            unsafe { *watch.stats.get() = ZERO };
+            watch.users.set(1);
+            watch.alloc.set(alloc);
+            counters.watching.set(counters.watching.get() + 1);
+            Some(slot)
+        })
+        .ok()
+        .and_then(|slot| slot)
+}
+
+/// Stops using a slot returned by `watch`, freeing it once it has no users.
+fn unwatch(slot: usize) {
+    let _ = COUNTERS.try_with(|counters| {
+        let watch = &counters.watches[slot];
+        let users = watch.users.get() - 1;
+        watch.users.set(users);
+        if users == 0 {
+            watch.alloc.set(0);
+            counters.watching.set(counters.watching.get() - 1);
+        }
+    });
+}
+
+/// Takes a snapshot of the statistics kept in a slot returned by `watch`.
+fn watched_stats(slot: usize) -> Stats {
+    // Safety: see `update`
+    COUNTERS
+        .try_with(|counters| unsafe { *counters.watches[slot].stats.get() })
+        .unwrap_or_default()
+}
+
+/// Returns a number identifying the calling thread among running threads.
+pub(crate) fn id() -> usize {
+    COUNTERS
+        .try_with(|counters| {
+            let counters: *const ThreadCounters = counters;
+            counters as usize
+        })
+        .unwrap_or(usize::MAX)
+}
+
+/// Takes a snapshot of the allocator statistics of the calling thread.
+///
+/// **These cover every instrumented allocator in the process**: each
+/// `StatsAlloc` records operations into the statistics of the thread which
+/// requested them. To count those of a single allocator, use a
+/// `ThreadRegion`.
+///
+/// Memory freed on a different thread from the one which allocated it is
+/// counted against the freeing thread, so `live_allocations` and `bytes_live`
+/// may be negative. `bytes_peak` is the highest `bytes_live` the thread has
+/// reached.
+pub fn thread_stats() -> Stats {
+    // Safety: see `update`
+    COUNTERS
+        .try_with(|counters| unsafe { *counters.stats.get() })
+        .unwrap_or_default()
+}
+
+/// Takes a snapshot of the combined allocator statistics of every thread which
+/// has exited.
+///
+/// **Like `thread_stats()`, these cover every instrumented allocator in the
+/// process.** Adding this to the `thread_stats()` of every running thread
+/// gives the statistics of all instrumented allocators. `bytes_peak` is the
+/// highest peak of any one exited thread.
+pub fn exited_thread_stats() -> Stats {
+    EXITED.load()
+}
+
+/// A snapshot of the allocation statistics of the calling thread for a single
+/// instrumented allocator, which can be used to determine allocation changes
+/// made by this thread through that allocator while the `ThreadRegion` is
+/// alive.
+///
+/// Unlike `Region`, allocations made by other threads are not included. A
+/// `ThreadRegion` cannot be sent to another thread.
+///
+/// Each thread keeps the statistics of up to eight allocators apart at once,
+/// shared by all of their regions. Beyond that, a region falls back to the
+/// statistics of every instrumented allocator, as `thread_stats()` returns.
+/// The statistics of an allocator are only kept while one of its regions is
+/// alive, so `initial()` starts from zero rather than reporting its earlier
+/// use by the thread.
+///
+/// ```
+/// # use stats_alloc::{StatsAlloc, ThreadRegion, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// let background = std::thread::spawn(|| {
+///     for _ in 0..1_000 {
+///         drop(vec![0u8; 4_096]);
+///     }
+/// });
+/// let reg = ThreadRegion::new(GLOBAL);
+/// let x: Vec<u8> = Vec::with_capacity(1_024);
+/// assert_eq!(reg.change().bytes_allocated, 1_024);
+/// # drop((x, background.join()));
+/// ```
+#[derive(Debug)]
+pub struct ThreadRegion<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    /// The allocator stays borrowed so that its key is not reused.
+    alloc: PhantomData<&'a StatsAlloc<T, O>>,
+    slot: Option<usize>,
+    initial_stats: Stats,
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> ThreadRegion<'a, T, O> {
+    /// Creates a new region using statistics from the calling thread for the
+    /// given instrumented allocator.
+    #[inline]
+    pub fn new(alloc: &'a StatsAlloc<T, O>) -> Self {
+        let slot = watch(alloc.key());
+        let mut region = ThreadRegion {
+            alloc: PhantomData,
+            slot,
+            initial_stats: Stats::default(),
+            not_send: PhantomData,
+        };
+        region.initial_stats = region.latest();
+        region
+    }
+
+    /// Returns the statistics as of instantiation or the last reset.
+    #[inline]
+    pub fn initial(&self) -> Stats {
+        self.initial_stats
+    }
+
+    /// Returns the difference between the currently reported statistics of
+    /// the calling thread and those provided by `initial()`.
+    #[inline]
+    pub fn change(&self) -> Stats {
+        self.latest() - self.initial_stats
+    }
+
+    /// Returns the difference between the currently reported statistics of
+    /// the calling thread and those provided by `initial()`, resetting initial
+    /// to the latest reported statistics.
+    #[inline]
+    pub fn change_and_reset(&mut self) -> Stats {
+        let latest = self.latest();
+        let diff = latest - self.initial_stats;
+        self.initial_stats = latest;
+        diff
+    }
+
+    /// Resets the initial statistics to the latest reported statistics of the
+    /// calling thread.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.initial_stats = self.latest();
+    }
+
+    fn latest(&self) -> Stats {
+        match self.slot {
+            Some(slot) => watched_stats(slot),
+            None => thread_stats(),
+        }
+    }
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> Drop for ThreadRegion<'a, T, O> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            unwatch(slot);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use display::Bytes;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{alloc::GlobalAlloc, cell::RefCell, fmt};
+use Region;
+use Stats;
+use StatsAlloc;
+
+/// Records named, nested regions as a call tree, keeping the statistics of
+/// each path through it, such as request → handler → serializer.
+///
+/// Entering a region within another makes it a child of that region, and
+/// regions of the same name entered under the same parent share a node whose
+/// calls are added up. A region is recorded when it is dropped, so regions
+/// must be dropped in the reverse order of entering them.
+///
+/// Each region measures every thread, as a `Region` does. The first time a
+/// name is entered under a parent, its node is allocated, which is counted in
+/// the enclosing regions; entering it again allocates nothing.
+///
+/// ```
+/// # use stats_alloc::{RegionTree, StatsAlloc, INSTRUMENTED_SYSTEM};
+/// # use std::alloc::System;
+/// #[global_allocator]
+/// static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+///
+/// let tree = RegionTree::new(GLOBAL);
+/// {
+///     let _request = tree.enter("request");
+///     let body = vec![0u8; 1_024];
+///     let _handler = tree.enter("handler");
+///     let reply = body.repeat(2);
+///     # drop(reply);
+/// }
+/// println!("{}", tree.report());
+/// ```
+#[derive(Debug)]
+pub struct RegionTree<'a, T: GlobalAlloc + 'a, O: 'a = ()> {
+    alloc: &'a StatsAlloc<T, O>,
+    state: RefCell<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    /// Nodes of the regions currently entered, outermost first.
+    open: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Node {
+    name: String,
+    children: Vec<usize>,
+    calls: u64,
+    inclusive: Stats,
+}
+
+impl<'a, T: GlobalAlloc + 'a, O: 'a> RegionTree<'a, T, O> {
+    /// Creates an empty tree of regions of the given instrumented allocator.
+    pub fn new(alloc: &'a StatsAlloc<T, O>) -> Self {
+        RegionTree {
+            alloc,
+            state: RefCell::new(State::default()),
+        }
+    }
+
+    /// Enters a region named `name`, as a child of the innermost region
+    /// entered and not yet dropped, if any.
+    pub fn enter<N: AsRef<str>>(&self, name: N) -> NamedRegion<'_, 'a, T, O> {
+        let node = self.state.borrow_mut().enter(name.as_ref());
+        NamedRegion {
+            tree: self,
+            node,
+            region: Region::new(self.alloc),
+        }
+    }
+
+    /// Returns the statistics recorded for each node so far.
+    ///
+    /// Regions which have not yet been dropped are left out of the statistics
+    /// of their nodes.
+    pub fn report(&self) -> RegionReport {
+        let state = self.state.borrow();
+        let mut nodes = Vec::with_capacity(state.nodes.len());
+        for &root in &state.roots {
+            state.report(root, 0, &mut nodes);
+        }
+        RegionReport { nodes }
+    }
+}
+
+impl State {
+    fn enter(&mut self, name: &str) -> usize {
+        let siblings = match self.open.last() {
+            Some(&parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        };
+        let node = match siblings.iter().find(|&&node| self.nodes[node].name == name) {
+            Some(&node) => node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(Node {
+                    name: name.to_owned(),
+                    children: Vec::new(),
+                    calls: 0,
+                    inclusive: Stats::default(),
+                });
+                match self.open.last() {
+                    Some(&parent) => self.nodes[parent].children.push(node),
+                    None => self.roots.push(node),
+                }
+                node
+            },
+        };
+        self.open.push(node);
+        node
+    }
+
+    fn exit(&mut self, node: usize, change: Stats) {
+        let entry = &mut self.nodes[node];
+        entry.calls += 1;
+        entry.inclusive += change;
+        if let Some(position) = self.open.iter().rposition(|&open| open == node) {
+            self.open.truncate(position);
+        }
+    }
+
+    fn report(&self, node: usize, depth: usize, nodes: &mut Vec<ReportNode>) {
+        let entry = &self.nodes[node];
+        let children: Stats = entry.children.iter().map(|&child| &self.nodes[child].inclusive).sum();
+        nodes.push(ReportNode {
+            name: entry.name.clone(),
+            depth,
+            calls: entry.calls,
+            inclusive: entry.inclusive,
+            exclusive: entry.inclusive.saturating_sub(children),
+        });
+        for &child in &entry.children {
+            self.report(child, depth + 1, nodes);
+        }
+    }
+}
+
+/// A region entered in a `RegionTree`, which records its change in the tree
+/// when dropped.
+#[derive(Debug)]
+pub struct NamedRegion<'t, 'a: 't, T: GlobalAlloc + 'a, O: 'a = ()> {
+    tree: &'t RegionTree<'a, T, O>,
+    node: usize,
+    region: Region<'a, T, O>,
+}
+
+impl<'t, 'a: 't, T: GlobalAlloc + 'a, O: 'a> NamedRegion<'t, 'a, T, O> {
+    /// Returns the change in the statistics since the region was entered.
+    #[inline]
+    pub fn change(&self) -> Stats {
+        self.region.change()
+    }
+}
+
+impl<'t, 'a: 't, T: GlobalAlloc + 'a, O: 'a> Drop for NamedRegion<'t, 'a, T, O> {
+    fn drop(&mut self) {
+        let change = self.region.change();
+        self.tree.state.borrow_mut().exit(self.node, change);
+    }
+}
+
+/// The statistics of each node of a `RegionTree`, in the order of a
+/// depth-first walk of the tree.
+///
+/// Printed, the report shows a line per node, indented by its depth, with
+/// its allocations inclusive of its children and those made by the node
+/// itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RegionReport {
+    nodes: Vec<ReportNode>,
+}
+
+impl RegionReport {
+    /// Returns the nodes, each followed by its children.
+    pub fn nodes(&self) -> &[ReportNode] {
+        &self.nodes
+    }
+}
+
+/// The statistics of a node of a `RegionTree`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReportNode {
+    /// Name of the regions of the node
+    pub name: String,
+    /// Number of enclosing nodes
+    pub depth: usize,
+    /// Number of regions recorded in the node
+    pub calls: u64,
+    /// Change over the regions of the node, including their children
+    pub inclusive: Stats,
+    /// Change over the regions of the node, less that of their children
+    ///
+    /// `bytes_peak` is that of `inclusive`.
+    pub exclusive: Stats,
+}
+
+impl fmt::Display for RegionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const HEADINGS: [&str; 5] = ["calls", "allocations", "bytes", "self allocations", "self bytes"];
+        let cells: Vec<[String; 5]> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                [
+                    node.calls.to_string(),
+                    node.inclusive.allocations.to_string(),
+                    Bytes(node.inclusive.bytes_allocated as f64).to_string(),
+                    node.exclusive.allocations.to_string(),
+                    Bytes(node.exclusive.bytes_allocated as f64).to_string(),
+                ]
+            })
+            .collect();
+        let mut widths = HEADINGS.map(str::len);
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        for (heading, &width) in HEADINGS.iter().zip(&widths) {
+            write!(f, "{:>width$}  ", heading, width = width)?;
+        }
+        f.write_str("region")?;
+        for (node, row) in self.nodes.iter().zip(&cells) {
+            f.write_str("\n")?;
+            for (cell, &width) in row.iter().zip(&widths) {
+                write!(f, "{:>width$}  ", cell, width = width)?;
+            }
+            write!(f, "{:indent$}{}", "", node.name, indent = node.depth * 2)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "nightly")]
+#![feature(allocator_api)]
+
+extern crate stats_alloc;
+
+use stats_alloc::{Fault, Region, StatsAlloc};
+use std::{alloc::System, collections::TryReserveError};
+
+#[test]
+fn collection_is_measured_in_isolation() {
+    let alloc = StatsAlloc::new(System);
+    let reg = Region::new(&alloc);
+    let mut v = Vec::with_capacity_in(4, &alloc);
+    v.extend_from_slice(&[1u64, 2, 3, 4, 5]);
+    let grown = reg.change();
+    assert_eq!(grown.allocations, 1);
+    assert_eq!(grown.reallocations, 1);
+    assert_eq!(grown.grows, 1);
+    assert_eq!(grown.shrinks, 0);
+    assert_eq!(grown.bytes_allocated, (v.capacity() * 8) as u64);
+    assert_eq!(grown.bytes_live, (v.capacity() * 8) as i64);
+
+    v.shrink_to_fit();
+    let shrunk = reg.change();
+    assert_eq!(shrunk.reallocations, 2);
+    assert_eq!(shrunk.shrinks, 1);
+    assert_eq!(shrunk.bytes_live, 40);
+
+    drop(v);
+    let freed = reg.change();
+    assert_eq!(freed.deallocations, 1);
+    assert_eq!(freed.live_allocations, 0);
+    assert_eq!(freed.bytes_live, 0);
+    assert_eq!(freed.bytes_peak, 64);
+}
+
+#[test]
+fn grow_and_shrink_are_counted_per_thread() {
+    let alloc = StatsAlloc::new(System);
+    let before = stats_alloc::thread_stats();
+    let mut v: Vec<u8, _> = Vec::with_capacity_in(1, &alloc);
+    v.extend_from_slice(&[0; 16]);
+    v.truncate(1);
+    v.shrink_to_fit();
+    let change = stats_alloc::thread_stats() - before;
+    assert!(change.grows >= 1);
+    assert!(change.shrinks >= 1);
+}
+
+#[test]
+fn failures_are_reported_as_alloc_errors() {
+    let alloc = StatsAlloc::new(System);
+    alloc.set_hard_limit(64);
+    let mut v: Vec<u8, _> = Vec::new_in(&alloc);
+    let error: Result<(), TryReserveError> = v.try_reserve_exact(128);
+    assert!(error.is_err());
+
+    v.try_reserve_exact(32).unwrap();
+    let _faults = alloc.inject_faults(Fault::Nth(1));
+    assert!(v.try_reserve_exact(48).is_err());
+    assert_eq!(v.capacity(), 32);
+
+    let stats = alloc.stats();
+    assert_eq!(stats.failures, 2);
+    assert_eq!(stats.allocations, 1);
+    assert_eq!(stats.grows, 0);
+    assert_eq!(stats.bytes_live, 32);
+}
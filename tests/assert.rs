@@ -0,0 +1,57 @@
+#[macro_use]
+extern crate stats_alloc;
+
+use stats_alloc::{AllocGuard, Limits, OnExceeded, StatsAlloc, INSTRUMENTED_SYSTEM};
+use std::{alloc::System, panic};
+
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+
+#[test]
+fn no_alloc_passes_through_value() {
+    let values = [1u64, 2, 3];
+    let sum = assert_no_alloc!({ values.iter().sum::<u64>() });
+    assert_eq!(sum, 6);
+}
+
+#[test]
+fn allocations_within_limits() {
+    let v = assert_allocations!(max = 1, bytes <= 1_024, bytes_live = 1_024, {
+        Vec::<u8>::with_capacity(1_024)
+    });
+    assert_eq!(v.capacity(), 1_024);
+}
+
+#[test]
+fn exceeded_limits_are_reported() {
+    let message = panic::catch_unwind(|| {
+        assert_allocations!(max = 1, reallocations = 0, {
+            let mut v = vec![0u8; 16];
+            v.reserve_exact(1_024);
+            let other = Box::new(16usize);
+            v.len() + *other
+        })
+    })
+    .unwrap_err();
+    let message = message.downcast_ref::<String>().unwrap();
+    assert_eq!(
+        message,
+        "allocation limits exceeded:\n    allocations: 2 (at most 1)\n    reallocations: 1 (at most 0)"
+    );
+}
+
+#[test]
+fn guard_checks_on_drop() {
+    let guard = AllocGuard::with_action(GLOBAL, Limits::new().bytes_allocated(0), OnExceeded::Log);
+    let x: Vec<u8> = Vec::with_capacity(64);
+    let exceeded = guard.check().unwrap_err();
+    assert_eq!(exceeded.violations()[0].field, "bytes_allocated");
+    assert!(exceeded.violations()[0].actual >= 64);
+    drop(x);
+
+    let panicked = panic::catch_unwind(|| {
+        let _guard = AllocGuard::new(GLOBAL, Limits::new().allocations(0));
+        Box::new(1u8)
+    });
+    assert!(panicked.is_err());
+}
@@ -0,0 +1,121 @@
+extern crate stats_alloc;
+
+use stats_alloc::StatsAlloc;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[test]
+fn hard_limit_refuses_allocations() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(600, 8).unwrap();
+    alloc.set_hard_limit(1_000);
+    assert_eq!(alloc.hard_limit(), Some(1_000));
+    unsafe {
+        let first = alloc.alloc(layout);
+        assert!(!first.is_null());
+        assert!(alloc.alloc(layout).is_null());
+        assert!(alloc.alloc_zeroed(layout).is_null());
+        assert!(alloc.realloc(first, layout, 1_200).is_null());
+
+        let grown = alloc.realloc(first, layout, 1_000);
+        assert!(!grown.is_null());
+        let grown_layout = Layout::from_size_align(1_000, 8).unwrap();
+        let stats = alloc.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.reallocations, 1);
+        assert_eq!(stats.bytes_live, 1_000);
+        assert_eq!(stats.bytes_peak, 1_000);
+
+        alloc.clear_hard_limit();
+        assert_eq!(alloc.hard_limit(), None);
+        let second = alloc.alloc(layout);
+        assert!(!second.is_null());
+        alloc.dealloc(second, layout);
+        alloc.dealloc(grown, grown_layout);
+    }
+    assert_eq!(alloc.stats().bytes_live, 0);
+}
+
+#[test]
+fn shrinking_is_allowed_over_the_hard_limit() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(1_000, 8).unwrap();
+    unsafe {
+        let ptr = alloc.alloc(layout);
+        assert!(!ptr.is_null());
+        alloc.set_hard_limit(500);
+        let shrunk = alloc.realloc(ptr, layout, 800);
+        assert!(!shrunk.is_null());
+        assert_eq!(alloc.stats().bytes_live, 800);
+        assert_eq!(alloc.stats().failures, 0);
+        alloc.dealloc(shrunk, Layout::from_size_align(800, 8).unwrap());
+    }
+}
+
+static SOFT_LIMIT_CALLS: AtomicUsize = AtomicUsize::new(0);
+static SOFT_LIMIT_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+fn on_soft_limit(bytes_live: usize) {
+    SOFT_LIMIT_CALLS.fetch_add(1, Ordering::SeqCst);
+    SOFT_LIMIT_BYTES.store(bytes_live, Ordering::SeqCst);
+}
+
+#[test]
+fn soft_limit_invokes_callback_once() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(400, 8).unwrap();
+    alloc.set_soft_limit(1_000, on_soft_limit);
+    assert_eq!(alloc.soft_limit(), Some(1_000));
+    unsafe {
+        let ptrs: Vec<_> = (0..5).map(|_| alloc.alloc(layout)).collect();
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+        assert_eq!(SOFT_LIMIT_CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(SOFT_LIMIT_BYTES.load(Ordering::SeqCst), 1_200);
+        for ptr in ptrs {
+            alloc.dealloc(ptr, layout);
+        }
+    }
+    alloc.clear_soft_limit();
+    assert_eq!(alloc.soft_limit(), None);
+}
+
+/// Refuses every request for more than a kilobyte.
+struct Small;
+
+unsafe impl GlobalAlloc for Small {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > 1_024 {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+static FAILED_SOFT_LIMIT_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn on_failed_soft_limit(_bytes_live: usize) {
+    FAILED_SOFT_LIMIT_CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn failed_allocations_leave_soft_limit_alone() {
+    let alloc = StatsAlloc::new(Small);
+    alloc.set_soft_limit(1_000, on_failed_soft_limit);
+    let large = Layout::from_size_align(2_000, 8).unwrap();
+    let small = Layout::from_size_align(600, 8).unwrap();
+    unsafe {
+        assert!(alloc.alloc(large).is_null());
+        assert_eq!(FAILED_SOFT_LIMIT_CALLS.load(Ordering::SeqCst), 0);
+        let ptrs = [alloc.alloc(small), alloc.alloc(small)];
+        assert_eq!(FAILED_SOFT_LIMIT_CALLS.load(Ordering::SeqCst), 1);
+        for ptr in ptrs {
+            alloc.dealloc(ptr, small);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Region, Stats, StatsAlloc, StatsDelta};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+fn allocate(alloc: &StatsAlloc<System>, count: usize) {
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    for _ in 0..count {
+        unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+    }
+}
+
+#[test]
+fn stats_add_and_sum() {
+    let a = Stats {
+        allocations: 2,
+        bytes_live: -16,
+        bytes_peak: 64,
+        ..Stats::default()
+    };
+    let b = Stats {
+        allocations: 3,
+        bytes_live: 48,
+        bytes_peak: 32,
+        ..Stats::default()
+    };
+    let sum = a + b;
+    assert_eq!(sum.allocations, 5);
+    assert_eq!(sum.bytes_live, 32);
+    // Peaks are high-water marks, so the higher is kept
+    assert_eq!(sum.bytes_peak, 64);
+    assert_eq!([a, b].iter().sum::<Stats>(), sum);
+    assert_eq!(vec![a, b].into_iter().sum::<Stats>(), sum);
+}
+
+#[test]
+fn checked_and_saturating_sub() {
+    let alloc = StatsAlloc::new(System);
+    allocate(&alloc, 2);
+    let earlier = alloc.stats();
+    allocate(&alloc, 3);
+    let later = alloc.stats();
+
+    assert_eq!(later.checked_sub(earlier).unwrap().allocations, 3);
+    assert_eq!(earlier.checked_sub(later), None);
+    let saturated = earlier.saturating_sub(later);
+    assert_eq!(saturated.allocations, 0);
+    assert_eq!(later.saturating_sub(earlier), later - earlier);
+
+    // The operators wrap around instead, so that adding back restores
+    let wrapped = earlier - later;
+    assert_eq!(wrapped.allocations, 3u64.wrapping_neg());
+    assert_eq!(
+        wrapped + later,
+        Stats {
+            bytes_peak: later.bytes_peak,
+            ..earlier
+        }
+    );
+}
+
+#[test]
+fn regions_compare_in_either_direction() {
+    let alloc = StatsAlloc::new(System);
+    let changes: Vec<Stats> = [1, 4, 2]
+        .iter()
+        .map(|&count| {
+            let reg = Region::new(&alloc);
+            allocate(&alloc, count);
+            reg.change()
+        })
+        .collect();
+
+    let delta = changes[0].delta(&changes[1]);
+    assert_eq!(delta.allocations, -3);
+    assert_eq!(delta.bytes_allocated, -48);
+    assert_eq!(changes[1].delta(&changes[0]), -delta);
+
+    let total: StatsDelta = changes.iter().map(|&change| StatsDelta::from(change)).sum();
+    assert_eq!(total.allocations, 7);
+    assert_eq!(total.deallocations, 7);
+    assert_eq!(total.live_allocations, 0);
+    assert_eq!(
+        total - StatsDelta::from(changes[1]),
+        changes[0].delta(&Stats::default()) + StatsDelta::from(changes[2])
+    );
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "heap-profiler")]
+
+extern crate stats_alloc;
+
+use stats_alloc::{DhatOutput, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    fs,
+    thread,
+    time::Duration,
+};
+
+#[inline(never)]
+fn allocate_for_dhat(alloc: &StatsAlloc<System>, layout: Layout) -> *mut u8 {
+    unsafe { alloc.alloc(layout) }
+}
+
+#[test]
+fn profile_records_peak_and_lifetimes() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(1);
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptrs: Vec<_> = (0..3).map(|_| allocate_for_dhat(&alloc, layout)).collect();
+    thread::sleep(Duration::from_millis(5));
+    for &ptr in &ptrs[1..] {
+        unsafe { alloc.dealloc(ptr, layout) };
+    }
+
+    let profile = alloc.heap_profile();
+    assert_eq!(profile.peak_bytes(), 300);
+    assert!(profile.peak_time() <= profile.duration());
+    let sample = &profile.samples()[0];
+    assert_eq!(sample.max_bytes(), 300);
+    assert_eq!(sample.max_allocations(), 3);
+    assert_eq!(sample.bytes_at_peak(), 300);
+    assert_eq!(sample.allocations_at_peak(), 3);
+    assert_eq!(sample.bytes(), 100);
+    assert!(sample.total_lifetime() >= Duration::from_millis(15));
+
+    let mut json = Vec::new();
+    profile.write_dhat(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(
+        json.starts_with("{\"dhatFileVersion\":2,\"mode\":\"rust-heap\""),
+        "{}",
+        json
+    );
+    assert!(json.contains("\"tb\":300,\"tbk\":3,"), "{}", json);
+    assert!(json.contains("\"mb\":300,\"mbk\":3,\"gb\":300,\"gbk\":3,\"eb\":100,\"ebk\":1,"));
+    assert!(json.contains("\"ftbl\":[\n\"[root]\""));
+    assert!(json.contains("allocate_for_dhat"));
+    unsafe { alloc.dealloc(ptrs[0], layout) };
+}
+
+#[test]
+fn output_is_written_when_dropped() {
+    let alloc = StatsAlloc::new(System);
+    let path = env::temp_dir().join(format!("stats-alloc-dhat-{}.json", std::process::id()));
+    let output = DhatOutput::new(&alloc, &path);
+    assert_eq!(alloc.sample_interval(), Some(1));
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = allocate_for_dhat(&alloc, layout);
+    drop(output);
+
+    let json = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(json.contains("\"eb\":64,\"ebk\":1,"), "{}", json);
+    unsafe { alloc.dealloc(ptr, layout) };
+}
@@ -0,0 +1,52 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Stats, StatsTable};
+
+fn sample() -> Stats {
+    Stats {
+        allocations: 4,
+        deallocations: 2,
+        reallocations: 1,
+        bytes_allocated: 3 * 1024 * 1024,
+        bytes_deallocated: 512,
+        bytes_reallocated: -256,
+        live_allocations: 2,
+        bytes_live: 3 * 1024 * 1024 - 512,
+        bytes_peak: 3 * 1024 * 1024,
+        ..Stats::default()
+    }
+}
+
+#[test]
+fn derived_figures() {
+    let stats = sample();
+    assert_eq!(stats.net_bytes(), 3 * 1024 * 1024 - 512);
+    assert_eq!(stats.average_allocation_size(), 786_432.0);
+    assert_eq!(stats.reallocation_ratio(), 0.25);
+    assert_eq!(Stats::default().average_allocation_size(), 0.0);
+    assert_eq!(Stats::default().reallocation_ratio(), 0.0);
+}
+
+#[test]
+fn display_uses_binary_units() {
+    assert_eq!(
+        sample().to_string(),
+        "4 allocations (3.0 MiB), 2 deallocations (512 B), 1 reallocations (-256 B), 0 failures; 2 live allocations \
+         of 3.0 MiB (peak 3.0 MiB), 3.0 MiB net; average allocation 768.0 KiB, reallocation ratio 0.25"
+    );
+}
+
+#[test]
+fn table_aligns_columns() {
+    let table = StatsTable::new()
+        .column("before", Stats::default())
+        .column("after", sample())
+        .to_string();
+    println!("{}", table);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 14);
+    assert_eq!(lines[0], "                    before      after");
+    assert_eq!(lines[1], "allocations              0          4");
+    assert_eq!(lines[5], "bytes allocated        0 B    3.0 MiB");
+    assert_eq!(lines[13], "reallocation ratio    0.00       0.25");
+}
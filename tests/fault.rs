@@ -0,0 +1,99 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Fault, Region, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    thread,
+};
+
+/// Makes `count` requests for `size` bytes, returning which ones failed.
+fn failures(alloc: &StatsAlloc<System>, count: usize, size: usize) -> Vec<bool> {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    (0..count)
+        .map(|_| unsafe {
+            let ptr = alloc.alloc(layout);
+            if !ptr.is_null() {
+                alloc.dealloc(ptr, layout);
+            }
+            ptr.is_null()
+        })
+        .collect()
+}
+
+#[test]
+fn nth_and_every_nth() {
+    let alloc = StatsAlloc::new(System);
+    let reg = Region::new(&alloc);
+    {
+        let _faults = alloc.inject_faults(Fault::Nth(3));
+        assert_eq!(failures(&alloc, 5, 8), [false, false, true, false, false]);
+    }
+    {
+        let _faults = alloc.inject_faults(Fault::EveryNth(2));
+        assert_eq!(failures(&alloc, 5, 8), [false, true, false, true, false]);
+    }
+    assert_eq!(failures(&alloc, 5, 8), [false; 5]);
+    let change = reg.change();
+    assert_eq!(change.failures, 3);
+    assert_eq!(change.allocations, 12);
+    assert_eq!(change.bytes_live, 0);
+}
+
+#[test]
+fn nested_injections_resume_the_outer() {
+    let alloc = StatsAlloc::new(System);
+    let _outer = alloc.inject_faults(Fault::EveryNth(3));
+    assert_eq!(failures(&alloc, 2, 8), [false, false]);
+    {
+        let _inner = alloc.inject_faults(Fault::LargerThan(64));
+        assert_eq!(failures(&alloc, 2, 8), [false, false]);
+        assert_eq!(failures(&alloc, 1, 128), [true]);
+    }
+    // The outer injection counts on from its second request
+    assert_eq!(failures(&alloc, 4, 8), [true, false, false, true]);
+    assert_eq!(alloc.stats().failures, 3);
+}
+
+#[test]
+fn larger_than() {
+    let alloc = StatsAlloc::new(System);
+    let _faults = alloc.inject_faults(Fault::LargerThan(64));
+    assert_eq!(failures(&alloc, 2, 64), [false; 2]);
+    assert_eq!(failures(&alloc, 2, 65), [true; 2]);
+    unsafe {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = alloc.alloc(layout);
+        assert!(alloc.realloc(ptr, layout, 128).is_null());
+        alloc.dealloc(ptr, layout);
+    }
+    assert_eq!(alloc.stats().failures, 3);
+}
+
+#[test]
+fn random_is_reproducible() {
+    let alloc = StatsAlloc::new(System);
+    let fault = Fault::Random {
+        probability: 0.25,
+        seed: 42,
+    };
+    let first = {
+        let _faults = alloc.inject_faults(fault);
+        failures(&alloc, 1_000, 8)
+    };
+    let second = {
+        let _faults = alloc.inject_faults(fault);
+        failures(&alloc, 1_000, 8)
+    };
+    assert_eq!(first, second);
+    let failed = first.iter().filter(|&&failed| failed).count();
+    assert!(failed > 150 && failed < 350, "{} failures", failed);
+}
+
+#[test]
+fn thread_faults_ignore_other_threads() {
+    let alloc = StatsAlloc::new(System);
+    let _faults = alloc.inject_thread_faults(Fault::EveryNth(1));
+    let other = thread::scope(|scope| scope.spawn(|| failures(&alloc, 3, 8)).join().unwrap());
+    assert_eq!(other, [false; 3]);
+    assert_eq!(failures(&alloc, 3, 8), [true; 3]);
+}
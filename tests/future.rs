@@ -0,0 +1,63 @@
+extern crate stats_alloc;
+
+use stats_alloc::{InstrumentedFuture, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
+
+static ALLOC: StatsAlloc<System> = StatsAlloc::system();
+
+/// Wakes nothing, as futures are polled until they are ready.
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Polls `future` to completion on the calling thread.
+fn block_on<F: Future + ?Sized>(mut future: Pin<&mut F>) -> F::Output {
+    let waker = Waker::from(Arc::new(Noop));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn counts_allocations_across_polls_on_different_threads() {
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let mut polls = 0;
+    let mut future = Box::pin(InstrumentedFuture::new(poll_fn(move |cx| {
+        polls += 1;
+        let ptr = unsafe { ALLOC.alloc(layout) };
+        if polls < 3 {
+            unsafe { ALLOC.dealloc(ptr, layout) };
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(ptr as usize)
+    })));
+
+    // Allocations made between polls are not the future's
+    unsafe { ALLOC.dealloc(ALLOC.alloc(layout), layout) };
+    let waker = Waker::from(Arc::new(Noop));
+    let mut cx = Context::from_waker(&waker);
+    assert!(future.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(future.stats().allocations, 1);
+
+    let (ptr, stats) = thread::spawn(move || block_on(future.as_mut())).join().unwrap();
+    assert_eq!(stats.allocations, 3);
+    assert_eq!(stats.deallocations, 2);
+    assert_eq!(stats.bytes_allocated, 768);
+    assert_eq!(stats.bytes_live, 256);
+    assert_eq!(stats.bytes_peak, 256);
+    unsafe { ALLOC.dealloc(ptr as *mut u8, layout) };
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "tracing")]
+
+extern crate stats_alloc;
+extern crate tracing;
+extern crate tracing_subscriber;
+
+use stats_alloc::{AllocLayer, StatsAlloc, INSTRUMENTED_SYSTEM};
+use std::{
+    alloc::System,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    Event,
+    Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    Layer,
+};
+
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+
+/// Keeps the `allocations` field of the events of the `stats_alloc` target.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<String>>>);
+
+struct Allocations(Option<String>);
+
+impl Visit for Allocations {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "allocations" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Capture {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != "stats_alloc" {
+            return;
+        }
+        let mut allocations = Allocations(None);
+        event.record(&mut allocations);
+        self.0.lock().unwrap().extend(allocations.0);
+    }
+}
+
+#[test]
+fn empty_spans_report_no_allocations() {
+    let capture = Capture::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(AllocLayer::new())
+        .with(capture.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = tracing::info_span!("empty").entered();
+    });
+    assert_eq!(*capture.0.lock().unwrap(), ["0"]);
+}
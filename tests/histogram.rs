@@ -0,0 +1,61 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Histogram, Region, StatsAlloc, BUCKETS};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[test]
+fn buckets_are_powers_of_two() {
+    assert_eq!(Histogram::bucket_of(0), 0);
+    assert_eq!(Histogram::bucket_of(1), 1);
+    assert_eq!(Histogram::bucket_of(2), 2);
+    assert_eq!(Histogram::bucket_of(3), 2);
+    assert_eq!(Histogram::bucket_of(1_024), 11);
+    assert_eq!(Histogram::bucket_of(usize::MAX), BUCKETS - 1);
+    assert_eq!(Histogram::bucket_range(11), (1_024, 2_047));
+    assert_eq!(Histogram::bucket_range(BUCKETS - 1).1, usize::MAX);
+}
+
+#[test]
+fn region_reports_size_classes() {
+    let alloc = StatsAlloc::new(System);
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let large = Layout::from_size_align(100_000, 8).unwrap();
+    let reg = Region::new(&alloc);
+    unsafe {
+        let mut ptrs = Vec::new();
+        for _ in 0..98 {
+            ptrs.push(alloc.alloc(small));
+        }
+        let big = alloc.alloc_zeroed(large);
+        let grown = alloc.realloc(ptrs.pop().unwrap(), small, 100);
+        alloc.dealloc(big, large);
+        alloc.dealloc(grown, Layout::from_size_align(100, 8).unwrap());
+        for ptr in ptrs {
+            alloc.dealloc(ptr, small);
+        }
+    }
+    let sizes = reg.size_histogram();
+    assert_eq!(sizes.total(), 100);
+    assert_eq!(sizes.buckets()[Histogram::bucket_of(8)], 98);
+    assert_eq!(sizes.buckets()[Histogram::bucket_of(100)], 1);
+    assert_eq!(sizes.buckets()[Histogram::bucket_of(100_000)], 1);
+    assert_eq!(sizes.percentile(50.0), 15);
+    assert_eq!(sizes.percentile(99.0), 127);
+    assert_eq!(sizes.percentile(100.0), 131_071);
+    assert_eq!(Histogram::default().percentile(50.0), 0);
+}
+
+#[test]
+fn subtraction_wraps_or_saturates() {
+    let mut small = Histogram::new();
+    small.record(8);
+    let mut large = small;
+    large.record(8);
+    large.record(1_024);
+
+    assert_eq!(small.checked_sub(large), None);
+    assert_eq!(small.saturating_sub(large), Histogram::new());
+    let wrapped = small - large;
+    assert_eq!(wrapped.buckets()[Histogram::bucket_of(8)], u64::MAX);
+    assert_eq!(wrapped + large, small);
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "tracing")]
+
+extern crate stats_alloc;
+extern crate tracing;
+extern crate tracing_subscriber;
+
+use stats_alloc::{AllocLayer, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    Event,
+    Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+
+static ALLOC: StatsAlloc<System> = StatsAlloc::system();
+
+/// An event's parent span and fields.
+type Captured = (Option<String>, BTreeMap<String, String>);
+
+/// Keeps the events of the `stats_alloc` target.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<Captured>>>);
+
+struct Fields(BTreeMap<String, String>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_owned(), format!("{:?}", value));
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() != "stats_alloc" {
+            return;
+        }
+        let mut fields = Fields(BTreeMap::new());
+        event.record(&mut fields);
+        let parent = ctx.event_span(event).map(|span| span.name().to_owned());
+        self.0.lock().unwrap().push((parent, fields.0));
+    }
+}
+
+#[test]
+fn reports_allocations_of_each_span_on_close() {
+    let capture = Capture::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(AllocLayer::new())
+        .with(capture.clone());
+    let small = Layout::from_size_align(10, 1).unwrap();
+    let large = Layout::from_size_align(100, 1).unwrap();
+
+    let kept = tracing::subscriber::with_default(subscriber, || {
+        let request = tracing::info_span!("request").entered();
+        let buffer = unsafe { ALLOC.alloc(large) };
+        let kept = {
+            let _handler = tracing::info_span!("handler").entered();
+            unsafe { ALLOC.alloc(small) }
+        };
+        unsafe { ALLOC.dealloc(buffer, large) };
+        // Not counted, as the span has been exited
+        let outside = request.exit();
+        unsafe { ALLOC.dealloc(ALLOC.alloc(small), small) };
+        drop(outside);
+        kept
+    });
+    unsafe { ALLOC.dealloc(kept, small) };
+
+    let events = capture.0.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    let (ref parent, ref handler) = events[0];
+    assert_eq!(parent.as_ref().map(String::as_str), Some("request"));
+    assert_eq!(handler["span"], "\"handler\"");
+    assert_eq!(handler["allocations"], "1");
+    assert_eq!(handler["bytes_live"], "10");
+
+    let (ref parent, ref request) = events[1];
+    assert_eq!(*parent, None);
+    assert_eq!(request["span"], "\"request\"");
+    assert_eq!(request["allocations"], "2");
+    assert_eq!(request["deallocations"], "1");
+    assert_eq!(request["bytes_allocated"], "110");
+    assert_eq!(request["bytes_deallocated"], "100");
+    assert_eq!(request["bytes_live"], "10");
+}
@@ -0,0 +1,124 @@
+extern crate stats_alloc;
+
+use stats_alloc::{LeakCheck, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    fs,
+    process::Command,
+};
+
+const CHILD: &str = "STATS_ALLOC_LEAK_CHECK_CHILD";
+
+static ALLOC: StatsAlloc<System> = StatsAlloc::system();
+
+#[test]
+fn report_compares_allocated_and_deallocated_bytes() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    let path = env::temp_dir().join(format!("stats_alloc_leak_report_{}.txt", std::process::id()));
+    let before = unsafe { alloc.alloc(layout) };
+    let check = LeakCheck::new(&alloc).output_file(&path);
+
+    let leaked = unsafe { alloc.alloc(layout) };
+    let freed = unsafe { alloc.alloc(layout) };
+    unsafe {
+        alloc.dealloc(freed, layout);
+        // Allocations from before the check are not its concern
+        alloc.dealloc(before, layout);
+    }
+
+    let report = check.report();
+    assert_eq!(report.bytes_allocated, 256);
+    assert_eq!(report.bytes_deallocated, 256);
+    assert_eq!(report.allocations, 0);
+    assert_eq!(report.leaked_bytes(), 0);
+    drop(check);
+
+    // Exceeding the threshold leaves it to the caller to exit
+    let check = LeakCheck::new(&alloc).fail_above(0).output_file(&path);
+    let leaked_again = unsafe { alloc.alloc(layout) };
+    let report = check.finish();
+    assert_eq!(report.leaked_bytes(), 128);
+    assert!(report
+        .to_string()
+        .starts_with("leak check: 128 bytes in 1 allocations outstanding"));
+    assert!(fs::read_to_string(&path).unwrap().starts_with("leak check: 128 bytes"));
+    fs::remove_file(&path).unwrap();
+
+    unsafe {
+        alloc.dealloc(leaked, layout);
+        alloc.dealloc(leaked_again, layout);
+    }
+}
+
+#[test]
+fn exceeding_the_threshold_at_exit_fails_the_process() {
+    let path = env::temp_dir().join(format!("stats_alloc_leak_{}.txt", std::process::id()));
+    if env::var_os(CHILD).is_some() {
+        LeakCheck::new(&ALLOC)
+            .fail_above(512)
+            .exit_code(3)
+            .output_file(env::var_os(CHILD).unwrap())
+            .install();
+        // Reported once the test harness exits
+        unsafe { ALLOC.alloc(Layout::from_size_align(1_024, 8).unwrap()) };
+        return;
+    }
+
+    let status = Command::new(env::current_exe().unwrap())
+        .args([
+            "exceeding_the_threshold_at_exit_fails_the_process",
+            "--exact",
+            "--test-threads=1",
+        ])
+        .env(CHILD, &path)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    let report = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(
+        report.starts_with("leak check: 1024 bytes in 1 allocations outstanding"),
+        "{}",
+        report
+    );
+}
+
+#[cfg(feature = "heap-profiler")]
+#[test]
+fn outstanding_allocations_are_grouped_by_size() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(1);
+    let path = env::temp_dir().join(format!("stats_alloc_leak_outstanding_{}.txt", std::process::id()));
+    // Allocations from before the check are not outstanding
+    let before = Layout::from_size_align(64, 8).unwrap();
+    let kept = unsafe { alloc.alloc(before) };
+    let check = LeakCheck::new(&alloc).backtraces(false).output_file(&path);
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let large = Layout::from_size_align(4_096, 8).unwrap();
+    let ptrs: Vec<_> = (0..3)
+        .map(|_| unsafe { alloc.alloc(small) })
+        .chain(Some(unsafe { alloc.alloc(large) }))
+        .collect();
+
+    let report = check.report();
+    let outstanding: Vec<_> = report
+        .outstanding()
+        .iter()
+        .map(|outstanding| (outstanding.size, outstanding.allocations))
+        .collect();
+    assert_eq!(outstanding, [(4_096, 1), (16, 3)]);
+    assert!(report
+        .outstanding()
+        .iter()
+        .all(|outstanding| outstanding.backtrace.is_empty()));
+    assert!(report.to_string().contains("\n\n3 allocations of 16 bytes"));
+    drop(check);
+    fs::remove_file(&path).unwrap();
+
+    for (i, ptr) in ptrs.into_iter().enumerate() {
+        unsafe { alloc.dealloc(ptr, if i < 3 { small } else { large }) };
+    }
+    unsafe { alloc.dealloc(kept, before) };
+}
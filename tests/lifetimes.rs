@@ -0,0 +1,50 @@
+#![cfg(feature = "heap-profiler")]
+
+extern crate stats_alloc;
+
+use stats_alloc::{Histogram, Region, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn region_splits_freed_from_outlived() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(1);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let before = unsafe { alloc.alloc(layout) };
+
+    let reg = Region::new(&alloc);
+    let short = unsafe { alloc.alloc(layout) };
+    let kept = unsafe { alloc.alloc(layout) };
+    thread::sleep(Duration::from_millis(2));
+    unsafe {
+        alloc.dealloc(short, layout);
+        alloc.dealloc(before, layout);
+    }
+
+    let lifetimes = reg.lifetimes().expect("profiling is running");
+    // The allocation made before the region is not counted as freed within it
+    assert_eq!(lifetimes.freed.total(), 1);
+    assert!(lifetimes.freed.percentile(100.0) >= 2_000_000);
+    assert_eq!(lifetimes.outlived.total(), 1);
+    assert_eq!(alloc.lifetime_histogram().total(), 2);
+
+    let mut reg = reg;
+    reg.reset();
+    assert_eq!(reg.lifetimes().unwrap().outlived.total(), 0);
+    unsafe { alloc.dealloc(kept, layout) };
+    assert_eq!(reg.lifetimes().unwrap().freed, Histogram::new());
+}
+
+#[test]
+fn lifetimes_need_the_profiler() {
+    let alloc = StatsAlloc::new(System);
+    let reg = Region::new(&alloc);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+    assert!(reg.lifetimes().is_none());
+    assert_eq!(alloc.lifetime_histogram().total(), 0);
+}
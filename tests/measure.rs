@@ -0,0 +1,53 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Region, StatsAlloc, INSTRUMENTED_SYSTEM};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+
+#[test]
+fn example_using_measure() {
+    let (x, stats) = GLOBAL.measure(|| Vec::<u8>::with_capacity(1_024));
+    println!("Stats at 1: {:#?}", stats);
+    assert_eq!(x.capacity(), 1_024);
+}
+
+#[test]
+fn measure_keeps_the_result_alive() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let (ptr, stats) = alloc.measure(|| unsafe { alloc.alloc(layout) });
+    assert_eq!(stats.allocations, 1);
+    assert_eq!(stats.bytes_live, 64);
+
+    let mut reg = Region::new(&alloc);
+    let ((), stats) = reg.measure(|| unsafe { alloc.dealloc(ptr, layout) });
+    assert_eq!(stats.deallocations, 1);
+    assert_eq!(stats.bytes_live, -64);
+    assert_eq!(reg.change().deallocations, 0);
+}
+
+#[test]
+fn measure_repeated_skips_warmup() {
+    let alloc = StatsAlloc::new(System);
+    let mut calls = 0;
+    let repeated = alloc.measure_repeated(2, 5, || {
+        calls += 1;
+        // The first call, which is a warm-up, allocates far more than the rest
+        let count = if calls == 1 { 100 } else { calls % 2 + 1 };
+        for _ in 0..count {
+            let layout = Layout::from_size_align(calls * 8, 8).unwrap();
+            unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+        }
+    });
+    assert_eq!(calls, 7);
+    assert_eq!(repeated.iterations, 5);
+    assert_eq!(repeated.min.allocations, 1);
+    assert_eq!(repeated.median.allocations, 2);
+    assert_eq!(repeated.max.allocations, 2);
+    assert_eq!(repeated.min.bytes_allocated, 32);
+    assert_eq!(repeated.median.bytes_allocated, 48);
+    assert_eq!(repeated.max.bytes_allocated, 112);
+    assert_eq!(repeated.max.bytes_live, 0);
+}
@@ -0,0 +1,99 @@
+extern crate stats_alloc;
+
+use stats_alloc::{AllocObserver, StatsAlloc, ThreadRegion};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Keeps a log of every request it observes, allocating as it does so.
+struct Log(Mutex<Vec<String>>);
+
+impl AllocObserver for Log {
+    fn on_alloc(&self, _ptr: *mut u8, layout: Layout) {
+        self.push(format!("alloc {}", layout.size()));
+    }
+
+    fn on_alloc_zeroed(&self, _ptr: *mut u8, layout: Layout) {
+        self.push(format!("alloc_zeroed {}", layout.size()));
+    }
+
+    fn on_realloc(&self, _ptr: *mut u8, _new_ptr: *mut u8, layout: Layout, new_size: usize) {
+        self.push(format!("realloc {} {}", layout.size(), new_size));
+    }
+
+    fn on_dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        self.push(format!("dealloc {}", layout.size()));
+    }
+
+    fn on_alloc_failure(&self, layout: Layout) {
+        self.push(format!("alloc failed {}", layout.size()));
+    }
+
+    fn on_realloc_failure(&self, _ptr: *mut u8, layout: Layout, new_size: usize) {
+        self.push(format!("realloc failed {} {}", layout.size(), new_size));
+    }
+}
+
+impl Log {
+    fn push(&self, entry: String) {
+        self.0.lock().unwrap().push(entry);
+    }
+}
+
+#[test]
+fn observer_sees_every_request() {
+    let alloc = StatsAlloc::with_observer(System, Log(Mutex::new(Vec::new())));
+    alloc.set_hard_limit(100);
+    let small = Layout::from_size_align(10, 8).unwrap();
+    unsafe {
+        let a = alloc.alloc(small);
+        let b = alloc.alloc_zeroed(small);
+        let a = alloc.realloc(a, small, 20);
+        assert!(alloc.realloc(b, small, 200).is_null());
+        assert!(alloc.alloc(Layout::from_size_align(100, 8).unwrap()).is_null());
+        alloc.dealloc(a, Layout::from_size_align(20, 8).unwrap());
+        alloc.dealloc(b, small);
+    }
+    let log = alloc.observer().0.lock().unwrap();
+    assert_eq!(
+        *log,
+        [
+            "alloc 10",
+            "alloc_zeroed 10",
+            "realloc 10 20",
+            "realloc failed 10 200",
+            "alloc failed 100",
+            "dealloc 20",
+            "dealloc 10",
+        ]
+    );
+}
+
+/// Counts allocations, allocating on each one.
+struct Boxing(AtomicUsize);
+
+impl AllocObserver for Boxing {
+    fn on_alloc(&self, _ptr: *mut u8, _layout: Layout) {
+        let boxed = Box::new(self.0.fetch_add(1, Ordering::SeqCst));
+        drop(boxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: StatsAlloc<System, Boxing> = StatsAlloc::system_with_observer(Boxing(AtomicUsize::new(0)));
+
+#[test]
+fn allocating_observer_does_not_recurse() {
+    let before = GLOBAL.observer().0.load(Ordering::SeqCst);
+    let reg = ThreadRegion::new(&GLOBAL);
+    let x = Box::new([0u8; 64]);
+    let change = reg.change();
+    assert_eq!(change.allocations, 2);
+    assert_eq!(change.bytes_allocated, 64 + 8);
+    assert!(GLOBAL.observer().0.load(Ordering::SeqCst) > before);
+    drop(x);
+}
@@ -0,0 +1,145 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Region, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    ptr,
+    thread,
+};
+
+/// The system allocator, which refuses requests over a megabyte.
+struct Small;
+
+unsafe impl GlobalAlloc for Small {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > 1 << 20 {
+            return ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[test]
+fn live_bytes_follow_allocations() {
+    let alloc = StatsAlloc::new(System);
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let large = Layout::from_size_align(1_024, 8).unwrap();
+    unsafe {
+        let a = alloc.alloc(small);
+        let b = alloc.alloc_zeroed(large);
+        let stats = alloc.stats();
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.bytes_live, 1_040);
+
+        let a = alloc.realloc(a, small, 64);
+        assert_eq!(alloc.stats().bytes_live, 1_088);
+
+        alloc.dealloc(b, large);
+        alloc.dealloc(a, Layout::from_size_align(64, 8).unwrap());
+    }
+    let stats = alloc.stats();
+    assert_eq!(stats.live_allocations, 0);
+    assert_eq!(stats.bytes_live, 0);
+    assert_eq!(stats.bytes_peak, 1_088);
+}
+
+#[test]
+fn region_reports_peak_while_alive() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(4_096, 8).unwrap();
+    unsafe {
+        let before = alloc.alloc(layout);
+        alloc.dealloc(before, layout);
+
+        let mut reg = Region::new(&alloc);
+        assert_eq!(reg.peak(), 0);
+
+        let half = Layout::from_size_align(2_048, 8).unwrap();
+        let a = alloc.alloc(half);
+        let b = alloc.alloc(half);
+        alloc.dealloc(a, half);
+        alloc.dealloc(b, half);
+
+        let change = reg.change_and_reset();
+        assert_eq!(change.bytes_peak, 4_096);
+        assert_eq!(change.bytes_live, 0);
+        assert_eq!(change.live_allocations, 0);
+
+        let a = alloc.alloc(half);
+        assert_eq!(reg.change().bytes_peak, 2_048);
+        assert_eq!(reg.change().bytes_live, 2_048);
+        alloc.dealloc(a, half);
+        assert_eq!(reg.change().bytes_live, 0);
+    }
+    assert_eq!(alloc.stats().bytes_peak, 4_096);
+}
+
+#[test]
+fn regions_beyond_slots_fall_back_to_allocator_peak() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+        let p = alloc.alloc(layout);
+        alloc.dealloc(p, layout);
+    }
+    let regions: Vec<_> = (0..17).map(|_| Region::new(&alloc)).collect();
+    assert_eq!(regions[0].peak(), 0);
+    assert_eq!(regions[16].peak(), 256);
+}
+
+#[test]
+fn failed_allocations_leave_peak_alone() {
+    let alloc = StatsAlloc::new(Small);
+    let reg = Region::new(&alloc);
+    let small = Layout::from_size_align(1_024, 8).unwrap();
+    let huge = Layout::from_size_align(4 << 20, 8).unwrap();
+    unsafe {
+        let p = alloc.alloc(small);
+        assert!(alloc.alloc(huge).is_null());
+        assert!(alloc.realloc(p, small, 4 << 20).is_null());
+        alloc.dealloc(p, small);
+    }
+    let stats = alloc.stats();
+    assert_eq!(stats.failures, 2);
+    assert_eq!(stats.bytes_live, 0);
+    assert_eq!(stats.bytes_peak, 1_024);
+    assert_eq!(reg.peak(), 1_024);
+}
+
+#[test]
+fn live_bytes_are_exact_across_threads() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(1_024, 8).unwrap();
+    let ptrs: Vec<usize> = thread::scope(|scope| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    (0..100)
+                        .map(|_| unsafe { alloc.alloc(layout) } as usize)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+    });
+    assert_eq!(alloc.stats().bytes_live, 409_600);
+    // Freed on another thread from the one which allocated them
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr as *mut u8, layout) };
+    }
+
+    let stats = alloc.stats();
+    assert_eq!(stats.bytes_live, 0);
+    // Sharded counters estimate the peak from their own shard
+    let slack = if cfg!(feature = "sharded-counters") {
+        16 << 16
+    } else {
+        0
+    };
+    assert!(stats.bytes_peak <= 409_600);
+    assert!(stats.bytes_peak + slack >= 409_600);
+}
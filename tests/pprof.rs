@@ -0,0 +1,115 @@
+#![cfg(feature = "pprof")]
+
+extern crate flate2;
+extern crate stats_alloc;
+
+use flate2::read::GzDecoder;
+use stats_alloc::StatsAlloc;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::Read,
+};
+
+#[inline(never)]
+fn allocate_for_pprof(alloc: &StatsAlloc<System>, layout: Layout) -> *mut u8 {
+    unsafe { alloc.alloc(layout) }
+}
+
+/// Reads the fields of a protocol buffer message as (field, wire type, value)
+/// where the value is the integer or the bytes of the field.
+fn fields(mut bytes: &[u8]) -> Vec<(u64, u64, u64, &[u8])> {
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[0];
+            *bytes = &bytes[1..];
+            value |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte < 0x80 {
+                return value;
+            }
+        }
+    }
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = varint(&mut bytes);
+        let (field, wire_type) = (key >> 3, key & 7);
+        match wire_type {
+            0 => fields.push((field, wire_type, varint(&mut bytes), &[][..])),
+            2 => {
+                let len = varint(&mut bytes) as usize;
+                fields.push((field, wire_type, 0, &bytes[..len]));
+                bytes = &bytes[len..];
+            },
+            _ => panic!("unexpected wire type {}", wire_type),
+        }
+    }
+    fields
+}
+
+fn packed(mut bytes: &[u8]) -> Vec<u64> {
+    let mut values = Vec::new();
+    while !bytes.is_empty() {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[0];
+            bytes = &bytes[1..];
+            value |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        values.push(value);
+    }
+    values
+}
+
+#[test]
+fn writes_a_gzipped_profile_proto() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(1);
+    let layout = Layout::from_size_align(96, 8).unwrap();
+    let mut ptrs: Vec<_> = (0..2).map(|_| allocate_for_pprof(&alloc, layout)).collect();
+    unsafe { alloc.dealloc(ptrs.pop().unwrap(), layout) };
+
+    let mut compressed = Vec::new();
+    alloc.heap_profile().write_pprof(&mut compressed).unwrap();
+    let mut proto = Vec::new();
+    GzDecoder::new(&compressed[..]).read_to_end(&mut proto).unwrap();
+
+    let fields = fields(&proto);
+    let strings: Vec<_> = fields
+        .iter()
+        .filter(|field| field.0 == 6)
+        .map(|field| String::from_utf8(field.3.to_vec()).unwrap())
+        .collect();
+    assert_eq!(strings[0], "");
+    for name in &[
+        "alloc_objects",
+        "alloc_space",
+        "inuse_objects",
+        "inuse_space",
+        "count",
+        "bytes",
+    ] {
+        assert!(strings.iter().any(|string| string == name), "missing {}", name);
+    }
+    assert!(strings.iter().any(|string| string.contains("allocate_for_pprof")));
+    assert_eq!(fields.iter().filter(|field| field.0 == 1).count(), 4);
+    assert!(fields.iter().any(|field| field.0 == 4));
+    assert!(fields.iter().any(|field| field.0 == 5));
+    assert!(fields.iter().any(|field| field.0 == 12 && field.2 == 1));
+
+    let samples: Vec<_> = fields.iter().filter(|field| field.0 == 2).collect();
+    assert_eq!(samples.len(), 1);
+    let sample = self::fields(samples[0].3);
+    let values = packed(sample.iter().find(|field| field.0 == 2).unwrap().3);
+    assert_eq!(values, [2, 192, 1, 96]);
+    let locations = packed(sample.iter().find(|field| field.0 == 1).unwrap().3);
+    assert!(!locations.is_empty());
+
+    unsafe { alloc.dealloc(ptrs[0], layout) };
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "heap-profiler")]
+
+extern crate stats_alloc;
+
+use stats_alloc::StatsAlloc;
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[global_allocator]
+static GLOBAL: StatsAlloc<System> = StatsAlloc::system();
+
+#[inline(never)]
+fn allocate_for_profile(alloc: &StatsAlloc<System>, layout: Layout) -> *mut u8 {
+    unsafe { alloc.alloc(layout) }
+}
+
+#[test]
+fn every_allocation_is_sampled_at_interval_one() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(1);
+    assert_eq!(alloc.sample_interval(), Some(1));
+
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let large = Layout::from_size_align(1_024, 8).unwrap();
+    let ptrs: Vec<_> = (0..100).map(|_| allocate_for_profile(&alloc, small)).collect();
+    let big = allocate_for_profile(&alloc, large);
+
+    let profile = alloc.heap_profile();
+    assert_eq!(profile.sample_interval(), 1);
+    assert_eq!(profile.allocations(), 101);
+    assert_eq!(profile.bytes(), 100 * 16 + 1_024);
+    assert_eq!(profile.samples()[0].sampled_bytes(), 1_600);
+    assert_eq!(profile.samples()[0].sampled_allocations(), 100);
+
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr, small) };
+    }
+    let profile = alloc.heap_profile();
+    assert_eq!(profile.allocations(), 1);
+    assert_eq!(profile.bytes(), 1_024);
+
+    let big = unsafe { alloc.realloc(big, large, 4_096) };
+    assert_eq!(alloc.heap_profile().bytes(), 4_096);
+    unsafe { alloc.dealloc(big, Layout::from_size_align(4_096, 8).unwrap()) };
+    let profile = alloc.heap_profile();
+    assert_eq!(profile.allocations(), 0);
+    assert_eq!(profile.bytes(), 0);
+    // The reallocation is sampled as a new allocation
+    assert_eq!(profile.total_allocations(), 102);
+    assert_eq!(profile.total_bytes(), 1_600 + 1_024 + 4_096);
+
+    // The counters are unaffected by profiling
+    let stats = alloc.stats();
+    assert_eq!(stats.allocations, 101);
+    assert_eq!(stats.bytes_live, 0);
+}
+
+#[test]
+fn sampled_profile_estimates_live_bytes() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(4_096);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let ptrs: Vec<_> = (0..10_000).map(|_| allocate_for_profile(&alloc, layout)).collect();
+
+    let profile = alloc.heap_profile();
+    let sampled: usize = profile.samples().iter().map(|s| s.sampled_allocations()).sum();
+    assert!(sampled > 0 && sampled < 10_000, "{} samples", sampled);
+    let bytes = profile.bytes() as f64;
+    let actual = 256.0 * 10_000.0;
+    assert!(
+        (bytes - actual).abs() < actual * 0.3,
+        "estimated {} of {} bytes",
+        bytes,
+        actual
+    );
+
+    for ptr in ptrs {
+        unsafe { alloc.dealloc(ptr, layout) };
+    }
+    assert_eq!(alloc.heap_profile().bytes(), 0);
+}
+
+#[test]
+fn backtraces_resolve_to_the_caller() {
+    let alloc = StatsAlloc::new(System);
+    alloc.start_profiling(1);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = allocate_for_profile(&alloc, layout);
+
+    let profile = alloc.heap_profile();
+    let symbols = profile.samples()[0].resolve();
+    let first = symbols[0].name.as_ref().expect("tests are built with symbols");
+    assert!(first.contains("allocate_for_profile"), "{:#?}", symbols);
+    assert!(profile.to_string().contains("64 bytes in 1 allocations (1 sampled)"));
+
+    alloc.stop_profiling();
+    assert_eq!(alloc.sample_interval(), None);
+    assert_eq!(alloc.heap_profile().samples().len(), 0);
+    unsafe { alloc.dealloc(ptr, layout) };
+}
+
+#[test]
+fn global_allocator_can_profile_itself() {
+    GLOBAL.start_profiling(1);
+    let kept: Vec<Box<[u8; 48]>> = (0..10).map(|_| Box::new([0; 48])).collect();
+    // Resolving symbols allocates through the profiled allocator
+    let report = GLOBAL.heap_profile().to_string();
+    assert!(report.starts_with("heap profile: "));
+    assert!(GLOBAL
+        .heap_profile()
+        .samples()
+        .iter()
+        .any(|s| s.sampled_allocations() >= 10));
+    GLOBAL.stop_profiling();
+    drop(kept);
+}
@@ -0,0 +1,76 @@
+#[macro_use]
+extern crate stats_alloc;
+
+use stats_alloc::{PrometheusEncoder, Stats, StatsAlloc, INSTRUMENTED_SYSTEM};
+use std::{alloc::System, fmt, str};
+
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+
+/// A fixed buffer, so that writing to it cannot allocate.
+struct Buffer {
+    bytes: [u8; 8192],
+    len: usize,
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test]
+fn encodes_counters_and_gauges() {
+    let stats = Stats {
+        allocations: 12,
+        bytes_allocated: 4096,
+        bytes_live: -16,
+        ..Stats::default()
+    };
+    let mut text = String::new();
+    PrometheusEncoder::new()
+        .prefix("app_heap")
+        .labels(&[("service", "api"), ("path", "a\"b\\c\nd")])
+        .encode_stats(&stats, &mut text)
+        .unwrap();
+
+    let labels = "{service=\"api\",path=\"a\\\"b\\\\c\\nd\"}";
+    assert!(text.starts_with(
+        "# HELP app_heap_allocations_total Count of allocation operations\n# TYPE app_heap_allocations_total counter\n"
+    ));
+    assert!(text.contains(&format!("\napp_heap_allocations_total{} 12\n", labels)));
+    assert!(text.contains(&format!("\napp_heap_allocated_bytes_total{} 4096\n", labels)));
+    assert!(text.contains("# TYPE app_heap_live_bytes gauge\n"));
+    assert!(text.contains(&format!("\napp_heap_live_bytes{} -16\n", labels)));
+    assert!(!text.contains("# EOF"));
+}
+
+#[test]
+fn open_metrics_names_counter_families() {
+    let mut text = String::new();
+    PrometheusEncoder::new()
+        .open_metrics(true)
+        .encode_stats(&Stats::default(), &mut text)
+        .unwrap();
+    assert!(text.contains("# TYPE stats_alloc_allocations counter\nstats_alloc_allocations_total 0\n"));
+    assert!(text.ends_with("\n# EOF\n"));
+}
+
+#[test]
+fn encoding_does_not_allocate() {
+    let mut buffer = Buffer {
+        bytes: [0; 8192],
+        len: 0,
+    };
+    let labels = [("instance", "test")];
+    let encoder = PrometheusEncoder::new().labels(&labels);
+    assert_no_alloc!({ encoder.encode(GLOBAL, &mut buffer).unwrap() });
+    let text = str::from_utf8(&buffer.bytes[..buffer.len]).unwrap();
+    assert!(text.contains("stats_alloc_live_bytes{instance=\"test\"} "));
+}
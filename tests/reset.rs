@@ -0,0 +1,54 @@
+extern crate stats_alloc;
+
+use stats_alloc::{Region, StatsAlloc};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[test]
+fn take_returns_counters_and_zeroes_them() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let kept = unsafe { alloc.alloc(layout) };
+    unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+
+    let taken = alloc.take();
+    assert_eq!(taken.allocations, 2);
+    assert_eq!(taken.deallocations, 1);
+    assert_eq!(taken.bytes_allocated, 128);
+    assert_eq!(taken.bytes_peak, 128);
+
+    // Live memory is still described after the reset
+    let stats = alloc.stats();
+    assert_eq!(stats.allocations, 0);
+    assert_eq!(stats.bytes_allocated, 0);
+    assert_eq!(alloc.size_histogram().total(), 0);
+    assert_eq!(stats.live_allocations, 1);
+    assert_eq!(stats.bytes_live, 64);
+    assert_eq!(stats.bytes_peak, 64);
+
+    unsafe { alloc.dealloc(kept, layout) };
+    let stats = alloc.stats();
+    assert_eq!(stats.deallocations, 1);
+    assert_eq!(stats.live_allocations, 0);
+    assert_eq!(stats.bytes_live, 0);
+}
+
+#[test]
+fn regions_saturate_across_a_reset() {
+    let alloc = StatsAlloc::new(System);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..3 {
+        unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+    }
+    let mut reg = Region::new(&alloc);
+    alloc.reset();
+    unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+
+    let change = reg.change();
+    assert_eq!(change.allocations, 0);
+    assert_eq!(change.bytes_deallocated, 0);
+    assert_eq!(change.bytes_live, 0);
+
+    reg.reset();
+    unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+    assert_eq!(reg.change().allocations, 1);
+}
@@ -0,0 +1,63 @@
+extern crate serde_json;
+extern crate stats_alloc;
+
+use stats_alloc::{Sampler, StatsAlloc};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    thread,
+    time::Duration,
+};
+
+static ALLOC: StatsAlloc<System> = StatsAlloc::system();
+
+#[test]
+fn samples_rates_into_a_ring() {
+    let sampler = Sampler::start(&ALLOC, Duration::from_millis(1), 4).unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..10 {
+        unsafe { ALLOC.dealloc(ALLOC.alloc(layout), layout) };
+        thread::sleep(Duration::from_millis(2));
+    }
+    let series = sampler.stop();
+
+    // The ring keeps only the latest samples, and is not counted itself
+    let samples = series.samples();
+    assert_eq!(samples.len(), 4);
+    assert!(samples.windows(2).all(|pair| pair[0].elapsed < pair[1].elapsed));
+    let last = samples.last().unwrap().stats;
+    assert_eq!(last.allocations, 10);
+    assert_eq!(last.bytes_allocated, 640);
+    assert_eq!(last.bytes_live, 0);
+    assert_eq!(series.rates().len(), 3);
+    assert!(series.mean_rates().allocations >= 0.0);
+
+    let mut csv = Vec::new();
+    series.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("elapsed_seconds,allocations,deallocations,"));
+    assert_eq!(lines.count(), 4);
+
+    let mut json = Vec::new();
+    series.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json.as_array().unwrap().len(), 4);
+    assert_eq!(json[3]["allocations"], 10);
+    assert!(json[1]["allocations_per_second"].is_number());
+}
+
+#[test]
+fn reports_rates_while_running() {
+    static IDLE: StatsAlloc<System> = StatsAlloc::system();
+    let sampler = Sampler::start(&IDLE, Duration::from_millis(1), 16).unwrap();
+    while sampler.rates().is_none() {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(sampler.rates().unwrap().allocations, 0.0);
+    assert_eq!(sampler.latest().unwrap().stats.allocations, 0);
+    drop(sampler);
+    assert_eq!(IDLE.stats().allocations, 0);
+}
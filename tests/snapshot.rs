@@ -0,0 +1,63 @@
+#[cfg(feature = "serde")]
+extern crate serde_json;
+extern crate stats_alloc;
+
+#[cfg(feature = "serde")]
+use stats_alloc::Stats;
+use stats_alloc::{Snapshot, StatsAlloc};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+#[test]
+fn snapshots_subtract_like_regions() {
+    let alloc = StatsAlloc::new(System);
+    let before = Snapshot::new("before", &alloc);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+    let after = Snapshot::new("after", &alloc);
+
+    assert_eq!(after.label, "after");
+    assert!(after.timestamp >= before.timestamp);
+    let change = &after - &before;
+    assert_eq!(change.allocations, 1);
+    assert_eq!(change.bytes_allocated, 32);
+    // The earlier snapshot has the smaller counts, which stop at zero
+    assert_eq!((before - after).allocations, 0);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn snapshots_round_trip_through_json() {
+    let stats = Stats {
+        allocations: 3,
+        bytes_live: -8,
+        ..Stats::default()
+    };
+    let snapshot = Snapshot::from_stats("request", stats);
+
+    let json = serde_json::to_value(&snapshot).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["label"], "request");
+    assert!(json["timestamp"].as_i64().unwrap() > 0);
+    assert_eq!(json["stats"]["allocations"], 3);
+    assert_eq!(json["stats"]["bytes_live"], -8);
+
+    let loaded: Snapshot = serde_json::from_value(json).unwrap();
+    assert_eq!(loaded, snapshot);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn missing_fields_are_zero_and_unknown_versions_fail() {
+    let stats: Stats = serde_json::from_str(r#"{"allocations": 2}"#).unwrap();
+    assert_eq!(
+        stats,
+        Stats {
+            allocations: 2,
+            ..Stats::default()
+        }
+    );
+
+    let error =
+        serde_json::from_str::<Snapshot>(r#"{"version": 2, "label": "", "timestamp": 0, "stats": {}}"#).unwrap_err();
+    assert!(error.to_string().contains("unknown snapshot version 2"), "{}", error);
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "tags")]
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
+
+extern crate stats_alloc;
+
+use stats_alloc::{Stats, StatsAlloc, Tag, TagRegion};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static LAST_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Remembers the size of the last block requested from `System`.
+struct LastSize;
+
+unsafe impl GlobalAlloc for LastSize {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LAST_SIZE.store(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LAST_SIZE.store(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[test]
+fn deallocations_count_against_the_allocating_tag() {
+    let alloc = StatsAlloc::new(System);
+    let (parser, cache) = (Tag::new("parser"), Tag::new("cache"));
+    let layout = Layout::from_size_align(100, 16).unwrap();
+
+    let ptr = {
+        let _tag = parser.enter();
+        assert_eq!(Tag::current(), parser);
+        unsafe { alloc.alloc(layout) }
+    };
+    assert_eq!(Tag::current(), Tag::UNTAGGED);
+    assert_eq!(ptr as usize % 16, 0);
+    {
+        let _tag = cache.enter();
+        let ptr = unsafe { alloc.realloc(ptr, layout, 200) };
+        unsafe { alloc.dealloc(ptr, Layout::from_size_align(200, 16).unwrap()) };
+    }
+
+    let stats = alloc.tag_stats(parser);
+    assert_eq!(stats.allocations, 1);
+    assert_eq!(stats.reallocations, 1);
+    assert_eq!(stats.deallocations, 1);
+    assert_eq!(stats.bytes_allocated, 200);
+    assert_eq!(stats.bytes_deallocated, 200);
+    assert_eq!(stats.bytes_live, 0);
+    assert_eq!(stats.bytes_peak, 200);
+    assert_eq!(alloc.tag_stats(cache), Stats::default());
+
+    let mut total = alloc.stats();
+    let mut sum: Stats = Tag::all().into_iter().map(|tag| alloc.tag_stats(tag)).sum();
+    total.bytes_peak = 0;
+    sum.bytes_peak = 0;
+    assert_eq!(sum, total);
+}
+
+#[test]
+fn tag_region_measures_one_tag() {
+    let alloc = StatsAlloc::new(System);
+    let network = Tag::new("network");
+    assert_eq!(network.name(), "network");
+    assert_eq!(Tag::new("network"), network);
+
+    let mut reg = TagRegion::new(&alloc, network);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let untagged = unsafe { alloc.alloc(layout) };
+    let tagged = {
+        let _outer = network.enter();
+        {
+            let _inner = Tag::UNTAGGED.enter();
+            unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+        }
+        unsafe { alloc.alloc_zeroed(layout) }
+    };
+    let change = reg.change_and_reset();
+    assert_eq!(change.allocations, 1);
+    assert_eq!(change.bytes_live, 32);
+
+    unsafe { alloc.dealloc(tagged, layout) };
+    unsafe { alloc.dealloc(untagged, layout) };
+    assert_eq!(reg.change().deallocations, 1);
+    assert_eq!(reg.change().bytes_live, -32);
+}
+
+#[test]
+fn tags_take_one_byte_whatever_the_alignment() {
+    let alloc = StatsAlloc::new(LastSize);
+    let page = Layout::from_size_align(4_096, 4_096).unwrap();
+    let ptr = {
+        let _tag = Tag::new("pages").enter();
+        unsafe { alloc.alloc(page) }
+    };
+    assert_eq!(ptr as usize % 4_096, 0);
+    assert_eq!(LAST_SIZE.load(Ordering::SeqCst), 4_097);
+    assert_eq!(alloc.tag_stats(Tag::new("pages")).bytes_live, 4_096);
+    unsafe { alloc.dealloc(ptr, page) };
+    assert_eq!(LAST_SIZE.load(Ordering::SeqCst), 4_097);
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+fn grown_allocations_are_zeroed_past_the_old_tag() {
+    use std::alloc::Allocator;
+
+    let alloc = StatsAlloc::new(System);
+    let old_layout = Layout::from_size_align(16, 8).unwrap();
+    let new_layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = {
+        let _tag = Tag::new("zeroed").enter();
+        alloc.allocate(old_layout).unwrap()
+    };
+    assert_eq!(ptr.len(), 16);
+    let grown = unsafe { alloc.grow_zeroed(ptr.cast(), old_layout, new_layout) }.unwrap();
+    assert_eq!(grown.len(), 64);
+    let grown = grown.cast::<u8>();
+    assert!(unsafe { std::slice::from_raw_parts(grown.as_ptr().add(16), 48) }
+        .iter()
+        .all(|&byte| byte == 0));
+    unsafe { alloc.deallocate(grown, new_layout) };
+    assert_eq!(alloc.tag_stats(Tag::new("zeroed")).bytes_live, 0);
+}
+
+#[cfg(feature = "nightly")]
+#[test]
+fn realigned_allocations_keep_their_tag() {
+    use std::alloc::Allocator;
+
+    let alloc = StatsAlloc::new(System);
+    let serializer = Tag::new("serializer");
+    let mut v = {
+        let _tag = serializer.enter();
+        Vec::with_capacity_in(4, &alloc)
+    };
+    v.extend_from_slice(&[1u8, 2, 3, 4, 5]);
+
+    let old_layout = Layout::from_size_align(v.capacity(), 1).unwrap();
+    let new_layout = Layout::from_size_align(256, 64).unwrap();
+    let (ptr, len, ..) = v.into_raw_parts_with_alloc();
+    let grown = unsafe { alloc.grow(std::ptr::NonNull::new(ptr).unwrap(), old_layout, new_layout) }.unwrap();
+    let grown = grown.cast::<u8>();
+    assert_eq!(grown.as_ptr() as usize % 64, 0);
+    assert_eq!(
+        unsafe { std::slice::from_raw_parts(grown.as_ptr(), len) },
+        &[1, 2, 3, 4, 5]
+    );
+    unsafe { alloc.deallocate(grown, new_layout) };
+
+    let stats = alloc.tag_stats(serializer);
+    assert_eq!(stats.allocations, 1);
+    assert_eq!(stats.grows, 2);
+    assert_eq!(stats.bytes_live, 0);
+}
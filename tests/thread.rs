@@ -0,0 +1,95 @@
+extern crate stats_alloc;
+
+use stats_alloc::{exited_thread_stats, Region, StatsAlloc, ThreadRegion, INSTRUMENTED_SYSTEM};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{Arc, Barrier},
+    thread,
+};
+
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+
+#[test]
+fn thread_region_ignores_other_threads() {
+    let started = Arc::new(Barrier::new(2));
+    let finished = Arc::new(Barrier::new(2));
+    let background = {
+        let started = started.clone();
+        let finished = finished.clone();
+        thread::spawn(move || {
+            started.wait();
+            let x: Vec<u8> = Vec::with_capacity(4_096);
+            finished.wait();
+            drop(x);
+        })
+    };
+
+    let global = Region::new(GLOBAL);
+    let local = ThreadRegion::new(GLOBAL);
+    started.wait();
+    let x: Vec<u8> = Vec::with_capacity(1_024);
+    finished.wait();
+    let change = local.change();
+    assert_eq!(change.allocations, 1);
+    assert_eq!(change.bytes_allocated, 1_024);
+    assert_eq!(change.bytes_live, 1_024);
+    assert!(global.change().bytes_allocated >= 1_024 + 4_096);
+    drop(x);
+    background.join().unwrap();
+}
+
+#[test]
+fn thread_region_ignores_other_allocators() {
+    let other: StatsAlloc<System> = StatsAlloc::system();
+    let global = ThreadRegion::new(GLOBAL);
+    let local = ThreadRegion::new(&other);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+        let ptr = other.alloc(layout);
+        other.dealloc(ptr, layout);
+    }
+    assert_eq!(global.change().allocations, 0);
+    assert_eq!(local.change().allocations, 1);
+    assert_eq!(local.change().bytes_allocated, 256);
+
+    let nested = ThreadRegion::new(GLOBAL);
+    let x: Vec<u8> = Vec::with_capacity(1_024);
+    assert_eq!(nested.change().bytes_allocated, 1_024);
+    assert_eq!(global.change().bytes_allocated, 1_024);
+    assert_eq!(local.change().allocations, 1);
+    drop(x);
+}
+
+#[test]
+fn exited_threads_are_retained() {
+    let before = exited_thread_stats();
+    thread::spawn(|| {
+        let mut x: Vec<u8> = Vec::with_capacity(10_000);
+        x.reserve_exact(20_000);
+        x.push(1);
+        x.shrink_to_fit();
+    })
+    .join()
+    .unwrap();
+    let change = exited_thread_stats() - before;
+    assert!(change.allocations >= 1);
+    assert!(change.reallocations >= 2);
+    assert!(change.bytes_allocated >= 20_000);
+}
+
+#[test]
+fn exited_threads_may_free_memory_of_others() {
+    let before = exited_thread_stats();
+    let x: Vec<u8> = Vec::with_capacity(1 << 20);
+    thread::spawn(move || drop(x)).join().unwrap();
+    let change = exited_thread_stats().delta(&before);
+    assert!(change.bytes_deallocated >= 1 << 20);
+    // The freeing thread exited with negative live bytes, which are
+    // subtracted from those of the exited threads
+    assert!(
+        change.bytes_live <= -(1 << 19),
+        "bytes_live changed by {}",
+        change.bytes_live
+    );
+}
@@ -0,0 +1,87 @@
+extern crate stats_alloc;
+
+use stats_alloc::{RegionTree, StatsAlloc};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+fn allocate(alloc: &StatsAlloc<System>, size: usize) {
+    let layout = Layout::from_size_align(size, 1).unwrap();
+    unsafe { alloc.dealloc(alloc.alloc(layout), layout) };
+}
+
+#[test]
+fn reports_inclusive_and_exclusive_stats() {
+    let alloc = StatsAlloc::new(System);
+    let tree = RegionTree::new(&alloc);
+    {
+        let _request = tree.enter("request");
+        allocate(&alloc, 100);
+        for _ in 0..2 {
+            let handler = tree.enter("handler");
+            allocate(&alloc, 10);
+            {
+                let _serializer = tree.enter("serializer");
+                allocate(&alloc, 1);
+            }
+            assert_eq!(handler.change().allocations, 2);
+        }
+    }
+    {
+        let _idle = tree.enter("idle");
+    }
+
+    let report = tree.report();
+    let nodes = report.nodes();
+    let summary: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            (
+                node.name.as_str(),
+                node.depth,
+                node.calls,
+                node.inclusive.bytes_allocated,
+                node.exclusive.bytes_allocated,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("request", 0, 1, 122, 100),
+            ("handler", 1, 2, 22, 20),
+            ("serializer", 2, 2, 2, 2),
+            ("idle", 0, 1, 0, 0),
+        ]
+    );
+    assert_eq!(nodes[0].inclusive.allocations, 5);
+    assert_eq!(nodes[1].exclusive.allocations, 2);
+
+    let text = report.to_string();
+    let lines: Vec<_> = text.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].ends_with("self bytes  region"));
+    assert!(lines[1].ends_with("100 B  request"));
+    assert!(lines[2].ends_with("20 B    handler"));
+    assert!(lines[3].ends_with("2 B      serializer"));
+}
+
+#[test]
+fn regions_dropped_out_of_order_close_their_children() {
+    let alloc = StatsAlloc::new(System);
+    let tree = RegionTree::new(&alloc);
+    let outer = tree.enter("outer");
+    let inner = tree.enter("inner");
+    drop(outer);
+    let next = tree.enter("next");
+    drop((inner, next));
+
+    let names: Vec<_> = tree
+        .report()
+        .nodes()
+        .iter()
+        .map(|node| (node.name.clone(), node.depth))
+        .collect();
+    assert_eq!(
+        names,
+        [("outer".to_owned(), 0), ("inner".to_owned(), 1), ("next".to_owned(), 0)]
+    );
+}